@@ -0,0 +1,57 @@
+//! SH48 merged reconstruction benchmark on synthetic calibrations
+//!
+//! ```shell
+//! cargo bench --bench reconstruction --features parallel
+//! ```
+
+use std::{hint::black_box, path::PathBuf, sync::Arc};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use gmt_dos_clients_io::{
+    gmt_m2::M2RigidBodyMotions,
+    optics::{M1Modes, SensorData},
+};
+use gmt_ns_im::MergeReconstructor;
+use interface::{Data, Read, Update, Write};
+
+#[path = "../src/fixtures.rs"]
+mod fixtures;
+use fixtures::{N_SEGMENT, calibrations, pickle, random};
+
+// about the number of valid SH48 lenslets per segment, times 2 for the x and y slopes
+const N_SLOPE: usize = 2 * 700;
+
+/// Pickles a synthetic open-loop reconstructor with `n_mode` modes per segment
+fn calibration(name: &str, n_mode: usize, seed: u64) -> PathBuf {
+    pickle(name, calibrations(N_SLOPE, n_mode, seed)).unwrap()
+}
+
+fn reconstruction(c: &mut Criterion) {
+    let mut recon: MergeReconstructor<_, M2RigidBodyMotions, M1Modes> =
+        MergeReconstructor::builder()
+            .calibration(calibration("gmt-ns-im_bench_m2-rbm.pkl", 6, 1))
+            .calibration(calibration("gmt-ns-im_bench_m1-bm.pkl", 27, 100))
+            .build()
+            .unwrap();
+    let slopes = Arc::new(random(N_SEGMENT * N_SLOPE, 42));
+    c.bench_function("merge reconstructor update", |b| {
+        b.iter(|| {
+            <_ as Read<SensorData>>::read(&mut recon, Data::from(black_box(slopes.clone())));
+            recon.update();
+            black_box(<_ as Write<M1Modes>>::write(&mut recon));
+        })
+    });
+    // the estimate of the previous frame is still held by a reader and is copied
+    let mut held = <_ as Write<M1Modes>>::write(&mut recon);
+    c.bench_function("merge reconstructor update with held estimate", |b| {
+        b.iter(|| {
+            <_ as Read<SensorData>>::read(&mut recon, Data::from(black_box(slopes.clone())));
+            recon.update();
+            held = black_box(<_ as Write<M1Modes>>::write(&mut recon));
+        })
+    });
+    black_box(held);
+}
+
+criterion_group!(benches, reconstruction);
+criterion_main!(benches);
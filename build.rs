@@ -0,0 +1,36 @@
+use std::{fs, path::Path, process::Command};
+
+fn main() {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let mut revision = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    if git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty()) {
+        revision.push_str("-dirty");
+    }
+    println!("cargo::rustc-env=GIT_REVISION={revision}");
+    // the revision changes with HEAD, with the branch HEAD points to and with the packed refs,
+    // the dirty flag with the index and with the sources
+    let mut watch = vec![
+        ".git/HEAD".to_string(),
+        ".git/index".to_string(),
+        ".git/packed-refs".to_string(),
+        "src".to_string(),
+        "Cargo.toml".to_string(),
+    ];
+    if let Some(branch) = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.trim().strip_prefix("ref: ").map(str::to_string))
+    {
+        watch.push(format!(".git/{branch}"));
+    }
+    // a missing file would re-run the build script at every build
+    for path in watch.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo::rerun-if-changed={path}");
+    }
+}
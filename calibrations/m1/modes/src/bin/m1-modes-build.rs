@@ -0,0 +1,84 @@
+//! Builds the M1 mode files
+//!
+//! Loads the bending modes from `m1_singular_modes.pkl` and writes, with the FEM id as prefix:
+//!  * `{fem_id}_m1_mode_to_force.mat`: the mode to force matrices `B2F_{i}`,
+//!  * `{fem_id}_m1_bending_modes.ceo`: the first `n_mode` bending modes of each segment,
+//!  * `{fem_id}_m1_raw_bending_modes.ceo`: the raw modes of each segment,
+//!    padded with zeros to the largest number of raw modes.
+//!
+//! ```shell
+//! cargo r -r --bin m1-modes-build -- --fem $FEM_REPO
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Parser;
+use m1_modes::{ceo::CeoModes, fem_id, load};
+use matio_rs::MatFile;
+
+#[derive(Debug, Parser)]
+#[command(about = "Builds the M1 mode to force matrices and CEO mode files")]
+struct Cli {
+    /// M1 singular modes pickle file
+    #[arg(short, long, default_value = "m1_singular_modes.pkl")]
+    modes: PathBuf,
+    /// FEM directory, the output files are named after its id
+    #[arg(long, env = "FEM_REPO")]
+    fem: PathBuf,
+    /// number of bending modes per segment
+    #[arg(short, long, default_value_t = 27)]
+    n_mode: usize,
+    /// size of the CEO mode grid
+    #[arg(long, default_value_t = 256)]
+    n_grid: usize,
+    /// length of the CEO mode grid
+    #[arg(long, default_value_t = 8.5)]
+    length: f64,
+    /// directory where the files are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let fem_id =
+        fem_id(&cli.fem).with_context(|| format!("failed to get the FEM id from {:?}", cli.fem))?;
+    println!("FEM id: {fem_id}");
+    let modes = load(&cli.modes)?;
+    std::fs::create_dir_all(&cli.output_dir)?;
+
+    let path = cli
+        .output_dir
+        .join(format!("{fem_id}_m1_mode_to_force.mat"));
+    let mat_file = MatFile::save(&path)?;
+    for (m, i) in modes.iter().zip(1..) {
+        mat_file.var(format!("B2F_{i}"), &m.mode_2_force().into_owned())?;
+    }
+    println!("mode to force matrices saved to {path:?}");
+
+    let mut bending_modes = CeoModes::new(cli.n_grid, cli.length, cli.n_mode);
+    let n_raw_mode = modes
+        .iter()
+        .map(|m| m.n_raw_mode())
+        .max()
+        .unwrap_or_default();
+    let mut raw_modes = CeoModes::new(cli.n_grid, cli.length, n_raw_mode);
+    for (m, i) in modes.iter().zip(1..) {
+        println!("segment #{i}: interpolating the modes");
+        let nodes = m.mode_nodes();
+        bending_modes.push(&nodes, m.modes())?;
+        raw_modes.push(&nodes, m.raw_modes())?;
+    }
+    for (ceo_modes, name) in [
+        (bending_modes, "m1_bending_modes"),
+        (raw_modes, "m1_raw_bending_modes"),
+    ] {
+        let path = cli.output_dir.join(format!("{fem_id}_{name}.ceo"));
+        ceo_modes.dump(&path)?;
+        println!("CEO modes saved to {path:?}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Checks and exports the M1 bending modes
+//!
+//! Loads the bending modes from `m1_singular_modes.pkl` and the mode to force matrices `B2F_{i}`,
+//! checks the orthonormality of the modes and, with the FEM, that the mode forces
+//! do not move the segments (see the README),
+//! then writes the modes, the node coordinates and the mode to force matrices
+//! to `.mat`, `.npz` and parquet files.
+//!
+//! ```shell
+//! cargo r -r --bin m1-modes-export -- --mode-to-force 20230530_1756_m1_mode_to_force.mat
+//! ```
+//!
+//! The FEM is loaded from the `FEM_REPO` environment variable,
+//! the zero rigid body motion check is skipped if it is not set.
+
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use m1_modes::{export, load, load_mode_to_force, rbm_gains};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Mat,
+    Npz,
+    Parquet,
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "Checks and exports the M1 bending modes")]
+struct Cli {
+    /// M1 singular modes pickle file
+    #[arg(short, long, default_value = "m1_singular_modes.pkl")]
+    modes: PathBuf,
+    /// MATLAB file with the `B2F_{i}` mode to force matrices,
+    /// compared to the matrices of the modes pickle file
+    #[arg(long)]
+    mode_to_force: Option<PathBuf>,
+    /// largest error accepted by the checks
+    #[arg(short, long, default_value_t = 1e-6)]
+    tolerance: f64,
+    /// export formats
+    #[arg(short, long, value_enum, value_delimiter = ',', default_values = ["mat", "npz", "parquet"])]
+    formats: Vec<Format>,
+    /// directory where the files are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let modes = load(&cli.modes)?;
+    println!("segment  nodes  actuators  modes  raw modes  orthonormality error");
+    let mut failed = false;
+    for (m, i) in modes.iter().zip(1..) {
+        let error = m.orthonormality_error();
+        failed |= error > cli.tolerance;
+        println!(
+            "{i:>7}  {:>5}  {:>9}  {:>5}  {:>9}  {error:>20.3e}",
+            m.n_node(),
+            m.n_actuator(),
+            m.n_mode(),
+            m.n_raw_mode()
+        );
+    }
+
+    if let Some(path) = &cli.mode_to_force {
+        for (b2f, (m, i)) in load_mode_to_force(path)?.iter().zip(modes.iter().zip(1..)) {
+            let error = if b2f.shape() == m.mode_2_force().shape() {
+                (b2f - m.mode_2_force()).amax() / m.mode_2_force().amax()
+            } else {
+                f64::INFINITY
+            };
+            failed |= error > cli.tolerance;
+            println!("segment #{i}: B2F_{i} relative difference to the modes pickle: {error:.3e}");
+        }
+    }
+
+    if std::env::var_os("FEM_REPO").is_some() {
+        let mut fem = gmt_fem::FEM::from_env()?;
+        for (gain, (m, i)) in rbm_gains(&mut fem)?.iter().zip(modes.iter().zip(1..)) {
+            let error = m.rbm_error(gain.as_view());
+            failed |= error > cli.tolerance;
+            println!("segment #{i}: mode forces rigid body motion error: {error:.3e}");
+        }
+    } else {
+        println!("no FEM_REPO, skipping the zero rigid body motion check");
+    }
+
+    std::fs::create_dir_all(&cli.output_dir)?;
+    for format in &cli.formats {
+        match format {
+            Format::Mat => export::to_mat(&modes, cli.output_dir.join("m1_bending_modes.mat"))?,
+            Format::Npz => export::to_npz(&modes, cli.output_dir.join("m1_bending_modes.npz"))?,
+            Format::Parquet => export::to_parquet(&modes, &cli.output_dir)?,
+        }
+    }
+
+    if failed {
+        anyhow::bail!(
+            "the M1 bending modes failed the checks (tolerance: {:e})",
+            cli.tolerance
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! CEO mirror modes
+//!
+//! The modes of each segment are linearly interpolated from the surface nodes
+//! onto a regular grid of `n`x`n` points evenly spread over `[-L/2,L/2]`, `x` varying fastest,
+//! the same way as CEO `Mapping`; the grid points outside the nodes convex hull are set to 0.
+//!
+//! The modes are written to a `.ceo` file, in the format of CEO `Mapping::dump`:
+//!  * `Ni` (i32): the grid size `n`,
+//!  * `L` (f64): the grid length,
+//!  * `N_SET` (i32): the number of sets of modes,
+//!  * `N_MODE` (i32): the number of modes in each set,
+//!  * `s2b` (7xi32): the set index of each segment,
+//!  * `M` (f64): the modes, for each set, for each mode, the `n`x`n` grid.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use nalgebra::{DMatrix, DMatrixView};
+use spade::{DelaunayTriangulation, Point2, Triangulation};
+
+/// CEO mirror modes of the 7 segments
+#[derive(Debug, Clone)]
+pub struct CeoModes {
+    n: usize,
+    l: f64,
+    n_mode: usize,
+    s2b: Vec<i32>,
+    sets: Vec<Vec<f64>>,
+}
+
+impl CeoModes {
+    /// Empty modes with `n_mode` modes per segment on a `n`x`n` grid of length `l`
+    pub fn new(n: usize, l: f64, n_mode: usize) -> Self {
+        Self {
+            n,
+            l,
+            n_mode,
+            s2b: vec![],
+            sets: vec![],
+        }
+    }
+    /// Interpolates the modes `[n_node,_]` of the next segment given the node coordinates `[n_node,2+]`
+    ///
+    /// The first `n_mode` modes are kept and padded with zeros to `n_mode`
+    pub fn push(
+        &mut self,
+        nodes: &DMatrix<f64>,
+        modes: DMatrixView<'_, f64>,
+    ) -> anyhow::Result<()> {
+        let mut triangulation: DelaunayTriangulation<Point2<f64>> = DelaunayTriangulation::new();
+        // node index of each vertex
+        let mut vertex_nodes = vec![0; nodes.nrows()];
+        for i in 0..nodes.nrows() {
+            let handle = triangulation.insert(Point2::new(nodes[(i, 0)], nodes[(i, 1)]))?;
+            vertex_nodes[handle.index()] = i;
+        }
+
+        let barycentric = triangulation.barycentric();
+        let mut weights = vec![];
+        let n = self.n;
+        let n_mode = self.n_mode.min(modes.ncols());
+        let mut set = vec![0f64; n * n * self.n_mode];
+        for i in 0..n {
+            for j in 0..n {
+                let (x, y) = (self.coordinate(j), self.coordinate(i));
+                barycentric.get_weights(Point2::new(x, y), &mut weights);
+                let k = i * n + j;
+                for (m, mode) in modes.column_iter().take(n_mode).enumerate() {
+                    set[m * n * n + k] = weights
+                        .iter()
+                        .map(|(v, w)| w * mode[vertex_nodes[v.index()]])
+                        .sum();
+                }
+            }
+        }
+        self.s2b.push(self.sets.len() as i32);
+        self.sets.push(set);
+        Ok(())
+    }
+    fn coordinate(&self, i: usize) -> f64 {
+        self.l * (i as f64 / (self.n - 1) as f64 - 0.5)
+    }
+    /// Writes the modes to a `.ceo` file
+    pub fn dump(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut file = BufWriter::new(File::create(path.as_ref())?);
+        file.write_all(&(self.n as i32).to_le_bytes())?;
+        file.write_all(&self.l.to_le_bytes())?;
+        file.write_all(&(self.sets.len() as i32).to_le_bytes())?;
+        file.write_all(&(self.n_mode as i32).to_le_bytes())?;
+        for s2b in &self.s2b {
+            file.write_all(&s2b.to_le_bytes())?;
+        }
+        for x in self.sets.iter().flatten() {
+            file.write_all(&x.to_le_bytes())?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        // grid of 5x5 points over [-1,1], the triangle covers the grid points with x+y<=0
+        let (n, l, n_mode) = (5, 2., 3);
+        let nodes = DMatrix::from_row_slice(3, 2, &[-1., -1., 1., -1., -1., 1.]);
+        // x and 1 over the triangle, the 3rd mode is padded with zeros
+        let modes = DMatrix::from_column_slice(3, 2, &[-1., 1., -1., 1., 1., 1.]);
+        let mut ceo = CeoModes::new(n, l, n_mode);
+        ceo.push(&nodes, modes.as_view())?;
+        ceo.push(&nodes, modes.columns(1, 1))?;
+        let path = std::env::temp_dir().join("m1-modes_round_trip.ceo");
+        ceo.dump(&path)?;
+
+        let bytes = std::fs::read(&path)?;
+        let i32_at = |k: usize| i32::from_le_bytes(bytes[k..k + 4].try_into().unwrap());
+        let f64_at = |k: usize| f64::from_le_bytes(bytes[k..k + 8].try_into().unwrap());
+        assert_eq!(i32_at(0), n as i32);
+        assert_eq!(f64_at(4), l);
+        assert_eq!((i32_at(12), i32_at(16)), (2, n_mode as i32));
+        assert_eq!((i32_at(20), i32_at(24)), (0, 1));
+        let header = 28;
+        assert_eq!(bytes.len(), header + 8 * 2 * n_mode * n * n);
+        let mode = |set: usize, m: usize, x: f64, y: f64| {
+            let (i, j) = (
+                (y / l + 0.5) * (n - 1) as f64,
+                (x / l + 0.5) * (n - 1) as f64,
+            );
+            let k = (set * n_mode + m) * n * n + i as usize * n + j as usize;
+            f64_at(header + 8 * k)
+        };
+        for (x, y) in [(-0.5, -0.5), (0., -0.5), (-0.5, 0.)] {
+            assert!((mode(0, 0, x, y) - x).abs() < 1e-12);
+            assert!((mode(0, 1, x, y) - 1.).abs() < 1e-12);
+            assert_eq!(mode(0, 2, x, y), 0.);
+            assert!((mode(1, 0, x, y) - 1.).abs() < 1e-12);
+            assert_eq!(mode(1, 1, x, y), 0.);
+        }
+        // outside the convex hull
+        for (x, y) in [(0.5, 0.), (0., 0.5), (1., 1.), (0.5, 1.)] {
+            for (set, m) in [(0, 0), (0, 1), (1, 0)] {
+                assert_eq!(mode(set, m, x, y), 0.);
+            }
+        }
+        Ok(())
+    }
+}
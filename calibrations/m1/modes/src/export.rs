@@ -0,0 +1,141 @@
+//! Export of the M1 bending modes to interchange formats
+//!
+//! The same variables are written to MATLAB and NumPy files, for segment `i` in `[1,7]`:
+//!  * `modes_{i}`: bending modes `[n_node,n_mode]`,
+//!  * `raw_modes_{i}`: raw modes `[n_node,n_raw_mode]`,
+//!  * `mode_nodes_{i}`: surface node coordinates `[n_node,3]`,
+//!  * `actuator_nodes_{i}`: actuator coordinates `[n_actuator,3]`,
+//!  * `B2F_{i}`: mode to force matrix `[n_actuator,n_mode]`.
+//!
+//! The parquet files are tables in long format, one row per matrix entry.
+
+use std::{fs::File, path::Path, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, Float64Array, UInt8Array, UInt32Array},
+    record_batch::RecordBatch,
+};
+use matio_rs::MatFile;
+use nalgebra::DMatrix;
+use ndarray::{Array2, ShapeBuilder};
+use ndarray_npy::NpzWriter;
+use parquet::arrow::ArrowWriter;
+
+use crate::SegmentModes;
+
+/// Named matrices of all the segments
+fn variables(modes: &[SegmentModes]) -> Vec<(String, DMatrix<f64>)> {
+    modes
+        .iter()
+        .zip(1..)
+        .flat_map(|(m, i)| {
+            [
+                (format!("modes_{i}"), m.modes().into_owned()),
+                (format!("raw_modes_{i}"), m.raw_modes().into_owned()),
+                (format!("mode_nodes_{i}"), m.mode_nodes()),
+                (format!("actuator_nodes_{i}"), m.actuator_nodes()),
+                (format!("B2F_{i}"), m.mode_2_force().into_owned()),
+            ]
+        })
+        .collect()
+}
+
+/// Writes the matrices to a MATLAB file
+pub fn to_mat(modes: &[SegmentModes], path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let mat_file = MatFile::save(path.as_ref())?;
+    for (name, var) in variables(modes) {
+        mat_file.var(name, &var)?;
+    }
+    Ok(())
+}
+
+/// Writes the matrices to a NumPy `.npz` file
+pub fn to_npz(modes: &[SegmentModes], path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let mut npz = NpzWriter::new(File::create(path.as_ref())?);
+    for (name, var) in variables(modes) {
+        let array =
+            Array2::from_shape_vec((var.nrows(), var.ncols()).f(), var.as_slice().to_vec())?;
+        npz.add_array(name, &array)?;
+    }
+    npz.finish()?;
+    Ok(())
+}
+
+fn write_parquet(batch: RecordBatch, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let mut writer = ArrowWriter::try_new(File::create(path.as_ref())?, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Table of the entries of a matrix of each segment: segment, row, column and value
+fn matrix_table(matrices: &[DMatrix<f64>], row: &str, column: &str) -> anyhow::Result<RecordBatch> {
+    let mut segment = vec![];
+    let mut rows = vec![];
+    let mut columns = vec![];
+    let mut values = vec![];
+    for (m, i) in matrices.iter().zip(1u8..) {
+        for (k, &v) in m.iter().enumerate() {
+            segment.push(i);
+            rows.push((k % m.nrows()) as u32);
+            columns.push((k / m.nrows()) as u32);
+            values.push(v);
+        }
+    }
+    Ok(RecordBatch::try_from_iter([
+        ("segment", Arc::new(UInt8Array::from(segment)) as ArrayRef),
+        (row, Arc::new(UInt32Array::from(rows)) as ArrayRef),
+        (column, Arc::new(UInt32Array::from(columns)) as ArrayRef),
+        ("value", Arc::new(Float64Array::from(values)) as ArrayRef),
+    ])?)
+}
+
+/// Table of the coordinates of the nodes of each segment: segment, node, x, y and z
+fn nodes_table(nodes: &[DMatrix<f64>]) -> anyhow::Result<RecordBatch> {
+    let segment: Vec<u8> = nodes
+        .iter()
+        .zip(1u8..)
+        .flat_map(|(n, i)| vec![i; n.nrows()])
+        .collect();
+    let node: Vec<u32> = nodes.iter().flat_map(|n| 0..n.nrows() as u32).collect();
+    let coordinate = |j: usize| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            nodes
+                .iter()
+                .flat_map(|n| n.column(j).iter().cloned().collect::<Vec<_>>())
+                .collect::<Vec<f64>>(),
+        ))
+    };
+    Ok(RecordBatch::try_from_iter([
+        ("segment", Arc::new(UInt8Array::from(segment)) as ArrayRef),
+        ("node", Arc::new(UInt32Array::from(node)) as ArrayRef),
+        ("x", coordinate(0)),
+        ("y", coordinate(1)),
+        ("z", coordinate(2)),
+    ])?)
+}
+
+/// Writes the modes, the node coordinates and the mode to force matrices to parquet files
+/// in `dir`: `m1_bending_modes.parquet`, `m1_mode_nodes.parquet`,
+/// `m1_actuator_nodes.parquet` and `m1_mode_to_force.parquet`
+pub fn to_parquet(modes: &[SegmentModes], dir: impl AsRef<Path>) -> anyhow::Result<()> {
+    let dir = dir.as_ref();
+    let shapes: Vec<_> = modes.iter().map(|m| m.modes().into_owned()).collect();
+    write_parquet(
+        matrix_table(&shapes, "node", "mode")?,
+        dir.join("m1_bending_modes.parquet"),
+    )?;
+    let nodes: Vec<_> = modes.iter().map(|m| m.mode_nodes()).collect();
+    write_parquet(nodes_table(&nodes)?, dir.join("m1_mode_nodes.parquet"))?;
+    let nodes: Vec<_> = modes.iter().map(|m| m.actuator_nodes()).collect();
+    write_parquet(nodes_table(&nodes)?, dir.join("m1_actuator_nodes.parquet"))?;
+    let b2f: Vec<_> = modes
+        .iter()
+        .map(|m| m.mode_2_force().into_owned())
+        .collect();
+    write_parquet(
+        matrix_table(&b2f, "actuator", "mode")?,
+        dir.join("m1_mode_to_force.parquet"),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+//! M1 bending modes
+//!
+//! Bending modes, node coordinates and mode to force matrices of the M1 segments
+//! as saved in `m1_singular_modes.pkl` by the `m1-modes` binary of `gmt_dos-systems_m1-modes`.
+
+use std::{fs::File, path::Path};
+
+use anyhow::Context;
+use gmt_fem::{FEM, Switch};
+use matio_rs::MatFile;
+use nalgebra::{DMatrix, DMatrixView};
+use serde::Deserialize;
+
+pub mod ceo;
+pub mod export;
+
+/// Bending modes of an M1 segment
+///
+/// The matrices are saved in column-major order
+#[derive(Debug, Clone, Deserialize)]
+pub struct SegmentModes {
+    /// modes of the FEM static gain from actuator forces to axial displacements `[n_node,n_actuator]`
+    pub raw_modes: Vec<f64>,
+    /// bending modes without rigid body motions `[n_node,n_actuator-6]`
+    pub modes: Vec<f64>,
+    /// mode to force matrix `[n_actuator,n_actuator-6]`
+    pub mode_2_force: Vec<f64>,
+    /// coordinates `[x,y,z]` of the surface nodes
+    pub mode_nodes: Vec<Vec<f64>>,
+    /// coordinates `[x,y,z]` of the actuators
+    pub actuator_nodes: Vec<Vec<f64>>,
+    /// number of surface nodes and number of actuators
+    pub shape: (usize, usize),
+}
+
+impl SegmentModes {
+    pub fn n_node(&self) -> usize {
+        self.shape.0
+    }
+    pub fn n_actuator(&self) -> usize {
+        self.shape.1
+    }
+    /// Number of bending modes
+    pub fn n_mode(&self) -> usize {
+        self.modes.len() / self.n_node()
+    }
+    /// Number of raw modes
+    pub fn n_raw_mode(&self) -> usize {
+        self.raw_modes.len() / self.n_node()
+    }
+    /// Bending modes `[n_node,n_mode]`
+    pub fn modes(&self) -> DMatrixView<'_, f64> {
+        DMatrixView::from_slice(&self.modes, self.n_node(), self.n_mode())
+    }
+    /// Raw modes `[n_node,n_raw_mode]`
+    pub fn raw_modes(&self) -> DMatrixView<'_, f64> {
+        DMatrixView::from_slice(&self.raw_modes, self.n_node(), self.n_raw_mode())
+    }
+    /// Mode to force matrix `[n_actuator,n_mode]`
+    pub fn mode_2_force(&self) -> DMatrixView<'_, f64> {
+        DMatrixView::from_slice(&self.mode_2_force, self.n_actuator(), self.n_mode())
+    }
+    /// Coordinates of the surface nodes `[n_node,3]`
+    pub fn mode_nodes(&self) -> DMatrix<f64> {
+        DMatrix::from_fn(self.mode_nodes.len(), 3, |i, j| self.mode_nodes[i][j])
+    }
+    /// Coordinates of the actuators `[n_actuator,3]`
+    pub fn actuator_nodes(&self) -> DMatrix<f64> {
+        DMatrix::from_fn(self.actuator_nodes.len(), 3, |i, j| {
+            self.actuator_nodes[i][j]
+        })
+    }
+    /// Largest deviation of the Gram matrix of the bending modes from the identity
+    pub fn orthonormality_error(&self) -> f64 {
+        let modes = self.modes();
+        let gram = modes.transpose() * modes;
+        gram.iter()
+            .enumerate()
+            .map(|(k, g)| {
+                let (i, j) = (k % gram.nrows(), k / gram.nrows());
+                (g - if i == j { 1. } else { 0. }).abs()
+            })
+            .fold(0f64, f64::max)
+    }
+    /// Largest rigid body motion of the segment for the forces of a mode,
+    /// normalized by the norms of the forces and of the gain
+    ///
+    /// `rbm_gain` is the FEM static gain `[6,n_actuator]` from the segment actuator forces
+    /// to the segment rigid body motions
+    pub fn rbm_error(&self, rbm_gain: DMatrixView<'_, f64>) -> f64 {
+        let forces = self.mode_2_force();
+        let rbm = rbm_gain * forces;
+        let scale = rbm_gain.norm();
+        rbm.column_iter()
+            .zip(forces.column_iter())
+            .map(|(r, f)| r.norm() / (scale * f.norm()))
+            .fold(0f64, f64::max)
+    }
+}
+
+/// Loads the bending modes of the 7 segments
+pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<SegmentModes>> {
+    let path = path.as_ref();
+    let modes: Vec<SegmentModes> = serde_pickle::from_reader(
+        File::open(path).with_context(|| format!("failed to open {path:?}"))?,
+        Default::default(),
+    )?;
+    Ok(modes)
+}
+
+/// Loads the mode to force matrices `B2F_{i}` of the 7 segments
+pub fn load_mode_to_force(path: impl AsRef<Path>) -> anyhow::Result<Vec<DMatrix<f64>>> {
+    let mat_file = MatFile::load(path.as_ref())?;
+    (1..=7)
+        .map(|i| Ok(mat_file.var(format!("B2F_{i}"))?))
+        .collect()
+}
+
+/// FEM static gains `[6,n_actuator]` from the actuator forces to the rigid body motions of each segment
+pub fn rbm_gains(fem: &mut FEM) -> anyhow::Result<Vec<DMatrix<f64>>> {
+    (1..=7)
+        .map(|i| {
+            fem.switch_inputs(Switch::Off, None)
+                .switch_outputs(Switch::Off, None);
+            fem.switch_inputs_by_name(vec![format!("M1_actuators_segment_{i}")], Switch::On)?
+                .switch_outputs_by_name(vec!["OSS_M1_lcl"], Switch::On)?;
+            let gain = fem
+                .reduced_static_gain()
+                .unwrap_or_else(|| fem.static_gain());
+            Ok(gain.rows(6 * (i - 1), 6).into_owned())
+        })
+        .collect()
+}
+
+/// FEM identifier, the date and time at the start of the name of the FEM directory,
+/// e.g. `20230530_1756` for `20230530_1756_zen_30_M1_202110_FSM_202305_Mount_202305_noStairs`
+pub fn fem_id(fem_path: impl AsRef<Path>) -> Option<String> {
+    let name = fem_path.as_ref().file_name()?.to_str()?;
+    let date_time: Vec<_> = name.split('_').take(2).collect();
+    (date_time.len() == 2).then(|| date_time.join("_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2 orthonormal bending modes over 4 nodes and 3 actuators,
+    /// the mode forces sum to zero
+    fn segment_modes() -> SegmentModes {
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let modes = vec![1., 0., 0., 0., 0., h, h, 0.];
+        SegmentModes {
+            raw_modes: modes.clone(),
+            modes,
+            mode_2_force: vec![1., -1., 0., 0., 1., -1.],
+            mode_nodes: vec![vec![0.; 3]; 4],
+            actuator_nodes: vec![vec![0.; 3]; 3],
+            shape: (4, 3),
+        }
+    }
+
+    #[test]
+    fn fem_ids() {
+        assert_eq!(
+            fem_id("/fems/20230530_1756_zen_30_M1_202110_FSM_202305_Mount_202305_noStairs")
+                .as_deref(),
+            Some("20230530_1756")
+        );
+        assert_eq!(fem_id("/fems/20230530"), None);
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            assert_eq!(fem_id(OsStr::from_bytes(b"20230530_1756_\xff")), None);
+        }
+    }
+
+    #[test]
+    fn orthonormality() {
+        let mut m = segment_modes();
+        assert_eq!((m.n_mode(), m.n_raw_mode()), (2, 2));
+        assert!(m.orthonormality_error() < 1e-15);
+        // the 2nd mode is scaled by 2 and leans onto the 1st one: its squared norm is 4.25
+        m.modes[4] = 0.5;
+        m.modes[5..7].iter_mut().for_each(|x| *x *= 2.);
+        let error = m.orthonormality_error();
+        assert!((error - 3.25).abs() < 1e-12, "{error}");
+    }
+
+    #[test]
+    fn rigid_body_motions() {
+        let m = segment_modes();
+        // the segment moves with the sum of the forces
+        let gain = DMatrix::from_element(6, 3, 1.);
+        assert!(m.rbm_error(gain.as_view()) < 1e-15);
+        // the segment moves with the force of the 1st actuator
+        let gain = DMatrix::from_fn(6, 3, |i, j| if i == 0 && j == 0 { 1. } else { 0. });
+        let error = m.rbm_error(gain.as_view());
+        assert!(
+            (error - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-15,
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Conditioning of the SH48 merged reconstructor
+//!
+//! Prints the condition number of the merged calibration of each segment
+//! and writes the full diagnostics (singular values, normalization factors and
+//! cross-talk between calibrations) to `merge_diagnostics.json` and to CSV files,
+//! together with the noise propagation coefficients (`noise_propagation.csv`).
+//!
+//! ```shell
+//! cargo r -r --bin merge-diagnostics -- --svd-truncation 0,0,0,0,0,0,1
+//! ```
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use gmt_ns_im::MergeReconstructor;
+
+#[derive(Debug, Parser)]
+#[command(about = "Conditioning of the SH48 merged reconstructor")]
+struct Cli {
+    /// closed-loop calibration pickle files, merged in the given order
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_values = [
+            "calibrations/sh48/closed_loop_recon_sh48-to-m2-rbm.pkl",
+            "calibrations/sh48/closed_loop_recon_sh48-to-m1-bm.pkl"
+        ]
+    )]
+    calibrations: Vec<PathBuf>,
+    /// number of singular values discarded for each segment
+    #[arg(short, long, value_delimiter = ',')]
+    svd_truncation: Option<Vec<usize>>,
+    /// centroid noise RMS used to compute the expected RMS of the estimates
+    #[arg(long)]
+    centroid_noise: Option<f64>,
+    /// directory where the diagnostics are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let builder = MergeReconstructor::<_, (), ()>::builder().calibrations(&cli.calibrations);
+    let mut recon = if let Some(n) = cli.svd_truncation {
+        builder.svd_truncation(n)
+    } else {
+        builder
+    }
+    // the conditioning of rank deficient calibrations is reported instead of rejected
+    .build_unchecked()?;
+    println!("{recon}");
+
+    let diagnostics = recon.diagnostics();
+    println!("segment  condition #  truncated condition #  normalization factors");
+    for s in &diagnostics.segments {
+        let norms: Vec<_> = s.norms.iter().map(|x| format!("{x:.3e}")).collect();
+        println!(
+            "{:>7}  {:>11.3e}  {:>21.3e}  {}",
+            s.segment,
+            s.condition_number,
+            s.truncated_condition_number,
+            norms.join(", ")
+        );
+    }
+
+    std::fs::create_dir_all(&cli.output_dir)?;
+    diagnostics.to_json(cli.output_dir.join("merge_diagnostics.json"))?;
+    diagnostics.to_csv(&cli.output_dir)?;
+
+    let noise_propagation = recon.noise_propagation();
+    println!("{noise_propagation}");
+    noise_propagation.to_csv(
+        cli.output_dir.join("noise_propagation.csv"),
+        cli.centroid_noise,
+    )?;
+    Ok(())
+}
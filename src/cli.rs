@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use gmt_ns_im::SimConfig;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "GMT Natural Seeing Integrated Model")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Bootstraps the model and runs it in closed loop
+    Run(RunArgs),
+    /// Bootstraps the model without closing the loops
+    BootstrapOnly(RunArgs),
+    /// Validates the configuration and the model inputs without running the model
+    Check(ConfigArgs),
+    /// Post-processes the outputs of a finished run
+    Summarize(SummarizeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// simulation configuration file [default: sim.toml if it exists]
+    #[arg(short, long, env = "SIM_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// calibration data manifest file
+    #[arg(short, long, default_value = "calibrations/manifest.toml")]
+    pub manifest: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// closed-loop duration [s], overrides the configuration file
+    #[arg(short, long)]
+    pub duration: Option<usize>,
+    /// directory where the model outputs are written to
+    #[arg(short, long, default_value = ".")]
+    pub output_dir: PathBuf,
+    /// perturbation scenario file
+    #[arg(short, long, default_value = "scenarios/m2-rbm_step.toml")]
+    pub scenario: PathBuf,
+    /// feedback loops left open
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub open: Vec<FeedbackLoop>,
+    /// saved merge reconstructor replacing the SH48 reconstructor whenever it is modified,
+    /// overrides the configuration file
+    #[arg(long)]
+    pub watch_sh48: Option<PathBuf>,
+    /// pickled reconstructor replacing the SH24 to FSM reconstructor whenever it is modified,
+    /// overrides the configuration file
+    #[arg(long)]
+    pub watch_sh24: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct SummarizeArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// directory where the outputs of the run have been written to
+    #[arg(short, long, default_value = ".")]
+    pub output_dir: PathBuf,
+    /// duration at the end of the run the statistics are computed over [s]
+    #[arg(short, long)]
+    pub last: Option<f64>,
+}
+
+/// Integrated model feedback loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FeedbackLoop {
+    /// AGWS SH24 to FSM piezostack actuators
+    Fsm,
+    /// AGWS SH48 M2 RBM estimates to M2 positioners
+    M2Positioner,
+    /// M1 edge sensors to M1 RBMs
+    M1EdgeSensors,
+    /// AGWS SH48 M1 bending modes estimates to M1 actuators
+    M1BendingModes,
+}
+
+impl RunArgs {
+    /// Loads, updates with the command line arguments and validates the simulation configuration
+    pub fn sim_config(&self) -> anyhow::Result<SimConfig> {
+        let mut sim_config = self.config.sim_config()?;
+        if let Some(duration) = self.duration {
+            sim_config.duration = duration;
+        }
+        if let Some(path) = &self.watch_sh48 {
+            sim_config.agws.sh48.reconstructor_watch = Some(path.clone());
+        }
+        if let Some(path) = &self.watch_sh24 {
+            sim_config.agws.sh24.reconstructor_watch = Some(path.clone());
+        }
+        for feedback in &self.open {
+            match feedback {
+                FeedbackLoop::Fsm => sim_config.agws.sh24.integrator_gain = 0.,
+                FeedbackLoop::M2Positioner => sim_config.fsm.offload_integrator_gain = 0.,
+                FeedbackLoop::M1EdgeSensors => sim_config.m1.edge_sensor.rbm_integrator_gain = 0.,
+                FeedbackLoop::M1BendingModes => sim_config.agws.sh48.integrator_gain = 0.,
+            }
+        }
+        sim_config.validate()?;
+        Ok(sim_config)
+    }
+}
+
+impl ConfigArgs {
+    /// Loads and validates the simulation configuration
+    pub fn sim_config(&self) -> anyhow::Result<SimConfig> {
+        let sim_config = SimConfig::load(self.config.as_ref())?;
+        sim_config.validate()?;
+        Ok(sim_config)
+    }
+}
@@ -0,0 +1,67 @@
+//! Synthetic calibrations shared by the tests and the benchmarks
+//!
+//! The slopes of each of the 7 segments are a separate block of `n_slope` entries of the sensor data.
+//! The integration tests and the benchmarks include this module with `#[path]`.
+
+#![allow(dead_code)]
+
+use std::{error::Error, fs::File, path::PathBuf};
+
+use gmt_dos_clients_crseo::calibration::{
+    Calib, CalibrationMode, Reconstructor, algebra::CalibProps,
+};
+
+pub const N_SEGMENT: usize = 7;
+
+/// Deterministic pseudo-random numbers in [-1,1]
+pub fn random(n: usize, seed: u64) -> Vec<f64> {
+    let mut state = seed;
+    (0..n)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            2. * (state >> 11) as f64 / (1u64 << 53) as f64 - 1.
+        })
+        .collect()
+}
+
+/// Random calibrations of `n_mode` modes per segment, the calibration of segment `i` is seeded with `seed+i`
+pub fn calibrations(n_slope: usize, n_mode: usize, seed: u64) -> Vec<Calib<CalibrationMode>> {
+    let n_data = N_SEGMENT * n_slope;
+    (0..N_SEGMENT)
+        .map(|i| {
+            let mask: Vec<bool> = (0..n_data).map(|k| k / n_slope == i).collect();
+            Calib::builder()
+                .c(random(n_slope * n_mode, seed + i as u64))
+                .n_cols(n_mode)
+                .mask(mask)
+                .mode(CalibrationMode::modes(n_mode, 1e-6))
+                .build()
+        })
+        .collect()
+}
+
+/// Synthetic linear sensor: the slopes of each segment are the product of its calibration with its modes
+pub fn sensor(calibs: &[Calib<CalibrationMode>], modes: &[f64]) -> Vec<f64> {
+    let n_mode = modes.len() / calibs.len();
+    calibs
+        .iter()
+        .zip(modes.chunks(n_mode))
+        .flat_map(|(calib, x)| {
+            let c = calib.as_slice();
+            let n_slope = c.len() / n_mode;
+            (0..n_slope)
+                .map(|r| (0..n_mode).map(|k| c[r + k * n_slope] * x[k]).sum::<f64>())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Pickles the open-loop reconstructor of the calibrations to `name` in the temporary directory
+pub fn pickle(name: &str, calibs: Vec<Calib<CalibrationMode>>) -> Result<PathBuf, Box<dyn Error>> {
+    let path = std::env::temp_dir().join(name);
+    let recon: Reconstructor<CalibrationMode, Calib<CalibrationMode>> = Reconstructor::new(calibs);
+    serde_pickle::to_writer(&mut File::create(&path)?, &recon, Default::default())?;
+    Ok(path)
+}
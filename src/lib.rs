@@ -6,12 +6,32 @@ pub enum MountEstimate {}
 #[cfg(feature = "scope")]
 pub mod scopes;
 
+#[cfg(test)]
+mod fixtures;
 pub mod m1_bending_modes;
+pub mod manifest;
 mod merge;
+pub mod noise_propagation;
+pub mod provenance;
 mod pseudo_open_loop;
-pub use merge::{MergeReconstructor, SplitEstimate};
+pub mod scenario;
+mod sim_config;
+mod swap_reconstructor;
+pub use merge::{
+    BadSlopes, CrossTalk, MaskedModes, MergeDiagnostics, MergeError, MergeReconstructor,
+    MergeReconstructorBuilder, MergedEstimate, ModeMask, NoiseCovariance, ReconstructorMatrix,
+    Regularization, RejectedSlopes, SegmentDiagnostics, SplitEstimate,
+};
 pub use pseudo_open_loop::{PseudoOpenLoop, PseudoSensorData};
+pub use sim_config::{
+    AgwsConfig, ConfigError, EdgeSensorConfig, FsmConfig, M1Config, SIM_CONFIG, Sh24Config,
+    Sh48Config, SimConfig,
+};
+pub use swap_reconstructor::SwapReconstructor;
 
+/// Default values of the integrated model parameters
+///
+/// Most of them can be overridden at runtime with a [SimConfig] file
 pub mod config {
     pub const ATMOSPHERE: bool = false;
     pub mod m1 {
@@ -35,6 +55,7 @@ pub mod config {
         }
         pub mod sh48 {
             pub const RATE: usize = 5000;
+            pub const INTEGRATOR_GAIN: f64 = 0.1;
         }
     }
     pub mod fsm {
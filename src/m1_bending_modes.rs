@@ -1,53 +1,210 @@
-use std::{fs::File, path::Path, sync::Arc};
+use std::{error::Error, fmt::Display, fs::File, io, path::Path, sync::Arc};
 
 use gmt_dos_clients_io::{
     gmt_m1::{M1ModeShapes, assembly::M1ModeCoefficients},
-    optics::{M1State, MirrorState},
+    optics::{M1Modes, M1State, MirrorState},
 };
 use gmt_dos_systems_m1::SingularModes;
-use interface::{Data, Read, Update, Write};
+use interface::{Data, Read, UID, Update, Write};
 
 use crate::config;
 
+/// RMS of the part of the surface of each M1 segment that is not spanned by the bending modes
+#[derive(UID)]
+pub enum M1ModeResidualRms {}
+
+/// Part of the surface of each M1 segment that is not spanned by the bending modes
+#[derive(UID)]
+pub enum M1ModeResidual {}
+
+#[derive(Debug)]
+pub enum M1ModesError {
+    Open(io::Error),
+    Pickle(serde_pickle::Error),
+    /// a segment has more modes than the coefficients of a segment
+    ModeCount {
+        segment: usize,
+        n_mode: usize,
+        max: usize,
+    },
+    /// the surfaces do not match the number of nodes of the segment modes
+    SurfaceSize {
+        expected: usize,
+        found: usize,
+    },
+    /// the coefficients are not evenly split between the segments or there are too many of them
+    CoefficientSize {
+        n_segment: usize,
+        max: usize,
+        found: usize,
+    },
+}
+
+impl Display for M1ModesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            M1ModesError::Open(error) => error.fmt(f),
+            M1ModesError::Pickle(error) => error.fmt(f),
+            M1ModesError::ModeCount {
+                segment,
+                n_mode,
+                max,
+            } => write!(
+                f,
+                "segment #{segment}: {n_mode} bending modes for at most {max} coefficients"
+            ),
+            M1ModesError::SurfaceSize { expected, found } => {
+                write!(f, "expected M1 surfaces of size {expected}, found {found}")
+            }
+            M1ModesError::CoefficientSize {
+                n_segment,
+                max,
+                found,
+            } => write!(
+                f,
+                "expected a multiple of {n_segment} M1 bending mode coefficients with at most {max} per segment, found {found}"
+            ),
+        }
+    }
+}
+impl Error for M1ModesError {}
+impl From<io::Error> for M1ModesError {
+    fn from(value: io::Error) -> Self {
+        Self::Open(value)
+    }
+}
+impl From<serde_pickle::Error> for M1ModesError {
+    fn from(value: serde_pickle::Error) -> Self {
+        Self::Pickle(value)
+    }
+}
+
+/// Loads the bending modes of the M1 segments
+///
+/// Each segment must have at most [N_RAW_MODE](config::m1::segment::N_RAW_MODE) modes
+fn load_modes(path: impl AsRef<Path>) -> Result<Vec<SingularModes>, M1ModesError> {
+    let modes: Vec<SingularModes> =
+        serde_pickle::from_reader(&mut File::open(path.as_ref())?, Default::default())?;
+    for (i, mode) in modes.iter().enumerate() {
+        let n_mode = mode.mat_ref().ncols();
+        if n_mode > config::m1::segment::N_RAW_MODE {
+            return Err(M1ModesError::ModeCount {
+                segment: i + 1,
+                n_mode,
+                max: config::m1::segment::N_RAW_MODE,
+            });
+        }
+    }
+    Ok(modes)
+}
+
+/// Projection of the M1 segment surfaces onto the bending modes
+///
+/// The coefficients of each segment are padded with zeros to
+/// [N_RAW_MODE](config::m1::segment::N_RAW_MODE).
+/// The surfaces are checked against the number of nodes of the modes of each segment,
+/// surfaces of the wrong size are rejected and the previous coefficients are kept,
+/// the rejection is reported once until surfaces of the right size are read.
+/// A [MirrorState] without modes gives a [MirrorState] without modes.
 #[derive(Debug, Default, Clone)]
 pub struct M1BendingModes {
     modes: Vec<SingularModes>,
     surfaces: Arc<Vec<f64>>,
     coefs: Arc<Vec<f64>>,
     state: Arc<MirrorState>,
+    residual_rms: Arc<Vec<f64>>,
+    // the residual surfaces are kept only if requested
+    keep_residual: bool,
+    residual: Arc<Vec<f64>>,
+    // the last update failed and has been reported
+    reported: bool,
 }
 
 impl M1BendingModes {
-    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let modes: Vec<SingularModes> =
-            serde_pickle::from_reader(&mut File::open(path.as_ref())?, Default::default())?;
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, M1ModesError> {
         Ok(Self {
-            modes,
+            modes: load_modes(path)?,
             ..Default::default()
         })
     }
+    /// Number of modes of each segment
+    pub fn mode_counts(&self) -> Vec<usize> {
+        self.modes
+            .iter()
+            .map(|mode| mode.mat_ref().ncols())
+            .collect()
+    }
+    /// Number of surface nodes of each segment
+    pub fn node_counts(&self) -> Vec<usize> {
+        self.modes
+            .iter()
+            .map(|mode| mode.mat_ref().nrows())
+            .collect()
+    }
+    /// Keeps the residual surfaces, written as [M1ModeResidual]
+    pub fn keep_residual(mut self) -> Self {
+        self.keep_residual = true;
+        self
+    }
+}
+impl M1BendingModes {
+    /// Projects the surfaces onto the bending modes
+    pub fn project(&mut self) -> Result<(), M1ModesError> {
+        if self.surfaces.is_empty() {
+            self.coefs = Default::default();
+            self.residual_rms = Arc::new(vec![0.; self.modes.len()]);
+            self.residual = Default::default();
+            return Ok(());
+        }
+        let expected: usize = self.node_counts().iter().sum();
+        if self.surfaces.len() != expected {
+            return Err(M1ModesError::SurfaceSize {
+                expected,
+                found: self.surfaces.len(),
+            });
+        }
+        let mut ns_acc = 0;
+        let mut coefs = Vec::with_capacity(self.modes.len() * config::m1::segment::N_RAW_MODE);
+        let mut residual_rms = Vec::with_capacity(self.modes.len());
+        let mut residual = Vec::new();
+        for mode in &self.modes {
+            let mat = mode.mat_ref();
+            let (ns, na) = mat.shape();
+            // let mat =
+            //     faer::mat::Mat::from_column_major_slice::<f64>(&mode.raw_modes, ns, na);
+            let deltas = faer::mat::MatRef::from_column_major_slice(
+                &self.surfaces[ns_acc..ns_acc + ns],
+                ns,
+                1,
+            );
+            ns_acc += ns;
+            let c = mat.transpose() * deltas;
+            // surface not spanned by the modes
+            let r = deltas - mat * &c;
+            let r = r.col_as_slice(0);
+            residual_rms.push((r.iter().map(|x| x * x).sum::<f64>() / ns as f64).sqrt());
+            if self.keep_residual {
+                residual.extend_from_slice(r);
+            }
+            coefs.extend_from_slice(c.col_as_slice(0));
+            coefs.extend(vec![0f64; config::m1::segment::N_RAW_MODE - na]);
+        }
+        self.coefs = Arc::new(coefs);
+        self.residual_rms = Arc::new(residual_rms);
+        self.residual = Arc::new(residual);
+        Ok(())
+    }
 }
 impl Update for M1BendingModes {
     fn update(&mut self) {
-        let mut ns_acc = 0;
-        self.coefs = Arc::new(
-            self.modes
-                .iter()
-                .flat_map(|mode| {
-                    let mat = mode.mat_ref();
-                    let (ns, na) = mat.shape();
-                    // let mat =
-                    //     faer::mat::Mat::from_column_major_slice::<f64>(&mode.raw_modes, ns, na);
-                    let deltas = &self.surfaces[ns_acc..ns_acc + ns];
-                    ns_acc += ns;
-                    let coefs =
-                        mat.transpose() * faer::mat::MatRef::from_column_major_slice(deltas, ns, 1);
-                    let mut coefs = coefs.col_as_slice(0).to_vec();
-                    coefs.extend(vec![0f64; config::m1::segment::N_RAW_MODE - na]);
-                    coefs
-                })
-                .collect(),
-        );
+        match self.project() {
+            Ok(()) => self.reported = false,
+            Err(e) if !self.reported => {
+                log::warn!("M1 bending modes: {e}");
+                self.reported = true;
+            }
+            Err(_) => (),
+        }
     }
 }
 
@@ -62,18 +219,198 @@ impl Write<M1ModeCoefficients> for M1BendingModes {
     }
 }
 
+impl Write<M1ModeResidualRms> for M1BendingModes {
+    fn write(&mut self) -> Option<Data<M1ModeResidualRms>> {
+        Some(self.residual_rms.clone().into())
+    }
+}
+/// The residual surfaces are empty unless they are kept with [M1BendingModes::keep_residual]
+impl Write<M1ModeResidual> for M1BendingModes {
+    fn write(&mut self) -> Option<Data<M1ModeResidual>> {
+        Some(self.residual.clone().into())
+    }
+}
+
 impl Read<M1State> for M1BendingModes {
     fn read(&mut self, data: Data<M1State>) {
         self.state = data.into_arc();
-        self.surfaces = self.state.modes.as_ref().unwrap().clone();
+        self.surfaces = self.state.modes.clone().unwrap_or_default();
     }
 }
 impl Write<M1State> for M1BendingModes {
     fn write(&mut self) -> Option<Data<M1State>> {
         let state = MirrorState {
             rbms: self.state.rbms.clone(),
-            modes: Some(self.coefs.clone()),
+            modes: (!self.coefs.is_empty()).then(|| self.coefs.clone()),
         };
         Some(Data::new(state))
     }
 }
+
+/// M1 segment surfaces from the bending mode coefficients
+///
+/// Reads either the [M1ModeCoefficients] (padded to
+/// [N_RAW_MODE](crate::config::m1::segment::N_RAW_MODE) per segment) or the SH48 [M1Modes] estimate
+/// and writes the [M1ModeShapes] surface displacements of each segment.
+/// The number of coefficients per segment is derived from the size of the input,
+/// coefficients of the wrong size are rejected, reported once, and the previous shapes are kept.
+#[derive(Debug, Default, Clone)]
+pub struct M1BendingModeShapes {
+    modes: Vec<SingularModes>,
+    coefs: Arc<Vec<f64>>,
+    shapes: Arc<Vec<f64>>,
+    // the last update failed and has been reported
+    reported: bool,
+}
+
+impl M1BendingModeShapes {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, M1ModesError> {
+        Ok(Self {
+            modes: load_modes(path)?,
+            ..Default::default()
+        })
+    }
+}
+impl From<&M1BendingModes> for M1BendingModeShapes {
+    fn from(value: &M1BendingModes) -> Self {
+        Self {
+            modes: value.modes.clone(),
+            ..Default::default()
+        }
+    }
+}
+impl M1BendingModeShapes {
+    /// Computes the surfaces of the segments from the coefficients
+    pub fn shapes(&mut self) -> Result<(), M1ModesError> {
+        let n_segment = self.modes.len().max(1);
+        let max = config::m1::segment::N_RAW_MODE;
+        let found = self.coefs.len();
+        if found % n_segment != 0 || found > n_segment * max {
+            return Err(M1ModesError::CoefficientSize {
+                n_segment,
+                max,
+                found,
+            });
+        }
+        let n = (found / n_segment).max(1);
+        self.shapes = Arc::new(
+            self.modes
+                .iter()
+                .zip(self.coefs.chunks(n))
+                .flat_map(|(mode, coefs)| {
+                    let mat = mode.mat_ref();
+                    let m = mat.ncols().min(coefs.len());
+                    let shape = mat.subcols(0, m)
+                        * faer::mat::MatRef::from_column_major_slice(&coefs[..m], m, 1);
+                    shape.col_as_slice(0).to_vec()
+                })
+                .collect(),
+        );
+        Ok(())
+    }
+}
+impl Update for M1BendingModeShapes {
+    fn update(&mut self) {
+        match self.shapes() {
+            Ok(()) => self.reported = false,
+            Err(e) if !self.reported => {
+                log::warn!("M1 bending mode shapes: {e}");
+                self.reported = true;
+            }
+            Err(_) => (),
+        }
+    }
+}
+
+impl Read<M1ModeCoefficients> for M1BendingModeShapes {
+    fn read(&mut self, data: Data<M1ModeCoefficients>) {
+        self.coefs = data.into_arc();
+    }
+}
+impl Read<M1Modes> for M1BendingModeShapes {
+    fn read(&mut self, data: Data<M1Modes>) {
+        self.coefs = data.into_arc();
+    }
+}
+impl Write<M1ModeShapes> for M1BendingModeShapes {
+    fn write(&mut self) -> Option<Data<M1ModeShapes>> {
+        Some(self.shapes.clone().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let mut m1_bms = M1BendingModes::new("calibrations/m1/modes/m1_singular_modes.pkl")?;
+        let mut m1_shapes = M1BendingModeShapes::from(&m1_bms);
+
+        let n = config::m1::segment::N_RAW_MODE;
+        let coefs: Vec<f64> = m1_bms
+            .modes
+            .iter()
+            .flat_map(|mode| {
+                let na = mode.mat_ref().ncols();
+                (0..n).map(move |i| {
+                    if i < na {
+                        1e-6 * ((i + 1) as f64).recip()
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        <_ as Read<M1ModeCoefficients>>::read(&mut m1_shapes, Data::new(coefs.clone()));
+        m1_shapes.update();
+        let shapes = <_ as Write<M1ModeShapes>>::write(&mut m1_shapes).unwrap();
+
+        <_ as Read<M1ModeShapes>>::read(&mut m1_bms, shapes);
+        m1_bms.update();
+        let round_trip = <_ as Write<M1ModeCoefficients>>::write(&mut m1_bms).unwrap();
+        let error = round_trip
+            .iter()
+            .zip(&coefs)
+            .map(|(c, x)| (c - x).abs())
+            .fold(0f64, f64::max);
+        assert!(error < 1e-12, "round-trip error: {error:e}");
+        Ok(())
+    }
+
+    #[test]
+    fn surface_size() -> anyhow::Result<()> {
+        let mut m1_bms = M1BendingModes::new("calibrations/m1/modes/m1_singular_modes.pkl")?;
+        let n: usize = m1_bms.node_counts().iter().sum();
+        <_ as Read<M1ModeShapes>>::read(&mut m1_bms, Data::new(vec![0f64; n - 1]));
+        assert!(matches!(
+            m1_bms.project(),
+            Err(M1ModesError::SurfaceSize { expected, found }) if expected == n && found == n - 1
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn coefficient_size() -> anyhow::Result<()> {
+        let mut m1_shapes =
+            M1BendingModeShapes::new("calibrations/m1/modes/m1_singular_modes.pkl")?;
+        let n_segment = m1_shapes.modes.len();
+        let max = config::m1::segment::N_RAW_MODE;
+        <_ as Read<M1Modes>>::read(&mut m1_shapes, Data::new(vec![0f64; n_segment]));
+        m1_shapes.shapes()?;
+        let n: usize = m1_shapes
+            .modes
+            .iter()
+            .map(|mode| mode.mat_ref().nrows())
+            .sum();
+        assert_eq!(m1_shapes.shapes.len(), n);
+        for found in [n_segment + 1, n_segment * (max + 1)] {
+            <_ as Read<M1Modes>>::read(&mut m1_shapes, Data::new(vec![0f64; found]));
+            assert!(matches!(
+                m1_shapes.shapes(),
+                Err(M1ModesError::CoefficientSize { found: f, .. }) if f == found
+            ));
+        }
+        Ok(())
+    }
+}
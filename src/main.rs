@@ -1,5 +1,6 @@
-use std::{fs::File, time::Instant};
+use std::{fs::File, path::Path, time::Instant};
 
+use clap::Parser;
 use faer::{Mat, MatRef};
 use gmt_dos_actors::{actorscript, system::Sys};
 use gmt_dos_clients::{
@@ -10,9 +11,9 @@ use gmt_dos_clients::{
     operator::{Left, Operator, Right},
     print::Print,
     sampler::Sampler,
-    signals::Signals,
     timer::Timer,
 };
+use gmt_dos_clients_arrow::Arrow;
 use gmt_dos_clients_crseo::{
     OpticalModel,
     calibration::{ClosedLoopCalib, Reconstructor},
@@ -29,8 +30,8 @@ use gmt_dos_clients_io::{
     },
     mount::{AverageMountEncoders, MountSetPoint},
     optics::{
-        M1State, M2State, SegmentPiston, SegmentTipTilt, SegmentWfeRms, SensorData, TipTilt,
-        Wavefront, WfeRms,
+        M1Modes, M1State, M2State, SegmentPiston, SegmentTipTilt, SegmentWfeRms, SensorData,
+        TipTilt, Wavefront, WfeRms,
     },
 };
 use gmt_dos_clients_lom::LinearOpticalModel;
@@ -49,402 +50,655 @@ use gmt_dos_systems_agws::{
 };
 use gmt_fem::FEM;
 use gmt_ns_im::{
-    MergeReconstructor, SplitEstimate, config, m1_bending_modes::M1BendingModes, scopes::*,
+    MergeReconstructor, RejectedSlopes, SimConfig, SwapReconstructor, config,
+    m1_bending_modes::{M1BendingModes, M1ModeResidualRms},
+    manifest::Manifest,
+    provenance::Provenance,
+    scenario::{Scenario, TimeBase},
+    scopes::*,
 };
 use interface::{Tick, units::Mas};
 use matio_rs::MatFile;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+mod cli;
+use cli::{Cli, Command, ConfigArgs, RunArgs, SummarizeArgs};
+
+/// On-axis star wavefront error RMS logs file name
+const ON_AXIS_WFE: &str = "on-axis_wfe.parquet";
+/// SH48 merged reconstructor cache
+const SH48_MERGE_RECON: &str = "calibrations/sh48/merge_recon_sh48-to-m2-rbm_m1-bm.bin";
+/// Run provenance record file name
+const RUN_PROVENANCE: &str = "run.json";
+
+/// Integrated model for a given set of SH48, SH24 and M1 actuators rates
+///
+/// The actorscript sampling rates must be literals, hence the model is monomorphized
+/// for each supported combination of rates with [rates].
+/// `$dollar` is the `$` token, it stands in for the actorscript logging marker `$`
+/// that cannot be written as is within a macro
+macro_rules! model {
+    ($dollar:tt, $sh48:tt, $sh24:tt, $m1:tt) => {
+        pub async fn run(
+            sim_config: &SimConfig,
+            scenario: &Scenario,
+            output_dir: &Path,
+            bootstrap_only: bool,
+            provenance: &mut Provenance,
+        ) -> anyhow::Result<()> {
+            let now = Instant::now();
+            let output = |file: &str| output_dir.join(file).to_string_lossy().into_owned();
+
+            let sim_sampling_frequency = sim_config.sampling_frequency;
+            let n_bootstrapping = sim_config.n_bootstrapping();
+            let n_sim = sim_config.n_sim();
+
+            let fem = FEM::from_env()?;
+            // println!("{fem}");
+
+            // let cfd_loads = Sys::<SigmoidCfdLoads>::try_from(
+            //     CfdLoads::foh(".", sim_sampling_frequency)
+            //         .duration((sim_duration + bootstrapping_duration) as f64)
+            //         .mount(&mut fem, 0, None)
+            //         .m1_segments()
+            //         .m2_segments(),
+            // )?;
+
+            // M1 EDGE SENSORS TO RIGID-BODY MOTIONS TRANSFORM
+            let m1_es_2_rbm: nalgebra::DMatrix<f64> =
+                MatFile::load("calibrations/m1/edge-sensors/es_2_rbm.mat")?.var("m1_r_es")?;
+            // let servos =
+            //     Sys::<GmtServoMechanisms<{ config::m1::ACTUATOR_RATE }, 1>>::from_data_repo_or_else(
+            //         "servos.bin",
+            //         || {
+            //             GmtServoMechanisms::<{ config::m1::ACTUATOR_RATE }, 1>::new(
+            //                 sim_sampling_frequency as f64,
+            //                 fem,
+            //             )
+            //             .m1_segment_figure(M1SegmentFigure::new())
+            //         },
+            //     )?;
+
+            let servos = GmtServoMechanisms::<$m1, 1>::new(sim_sampling_frequency as f64, fem)
+                .edge_sensors(EdgeSensors::m1().m1_with(m1_es_2_rbm))
+                // .wind_loads(WindLoads::new())
+                .m1_segment_figure(M1SegmentFigure::new())
+                .build()?;
+            println!("{servos}");
+            provenance.component("servos", &servos);
+            // serde_pickle::to_writer(
+            //     &mut File::create("servos.bin")?,
+            //     &servos,
+            //     Default::default(),
+            // )?;
+            // let rdr = BufReader::new(File::open("servos.bin")?);
+            // let servos :Sys< GmtServoMechanisms<{ config::m1::ACTUATOR_RATE }, 1>>=
+            // serde_pickle::from_reader(rdr, Default::default())?;
+
+            // AGWS
+            // the SH24 reconstructor is kept out of the SH24 kernel so that it can be hot-swapped
+            let mut sh24_recon = SwapReconstructor::new(serde_pickle::from_reader(
+                File::open("calibrations/sh24/recon_sh24-to-pzt_pth.pkl")?,
+                Default::default(),
+            )?);
+            if let Some(path) = &sim_config.agws.sh24.reconstructor_watch {
+                sh24_recon.watch(path);
+                provenance.input(path);
+            }
+            println!("SH24 to FSM reconstructor:\n{sh24_recon}");
+            provenance.component("sh24_to_fsm_recon", &sh24_recon);
+            let (agws_wss, mut agws): (_, Sys<Agws<$sh48, $sh24>>) = {
+                let agws = if sim_config.atmosphere {
+                    Agws::builder().load_atmosphere(
+                        "atmosphere/atmosphere.toml",
+                        sim_sampling_frequency as f64,
+                    )?
+                } else {
+                    Agws::builder()
+                        .sh24(
+                            ShackHartmannBuilder::sh24()
+                                // .source(AgwsGuideStar::sh24().zenith_azimuth(vec![0f32], vec![0f32]))
+                                .use_calibration_src(),
+                        )
+                        .sh48(ShackHartmannBuilder::sh48().use_calibration_src())
+                }
+                .gmt(Gmt::builder().m1(
+                    gmt_ns_im::config::m1::segment::RAW_MODES,
+                    gmt_ns_im::config::m1::segment::N_RAW_MODE,
+                ));
+                (agws.wave_sensor().build()?, agws.build()?)
+            };
+            if let Some(p24) = sim_config.sh24_pointing_error() {
+                let _ = agws.sh24_pointing(p24).await;
+            }
+            println!("{agws}");
+            println!("{agws_wss}");
+            provenance
+                .component("agws", &agws)
+                .component("agws_wss", &agws_wss);
+
+            // let sh48_frame: gif::Frame<f32> = gif::Frame::new("sh48_frame.png", 48 * 8);
+            // let sh24_frame: gif::Frame<f32> = gif::Frame::new("sh24_frame.png", 24 * 12);
+            // let on_axis_wavefront: gif::Frame<f64> = gif::Frame::new("on-axis_wavefront.png", 512);
+            provenance
+                .output(output("agws_wavefronts.png"))
+                .output(output("on-axis_wavefront.gif"));
+            let agws_wavefronts: gif::Frame<f64> =
+                gif::Frame::new(&output("agws_wavefronts.png"), 512);
+            let on_axis_wavefront: gif::Gif<f64> =
+                gif::Gif::new(&output("on-axis_wavefront.gif"), 512, 512)?.delay(200);
+
+            // FSM command integrator
+            let fsm_pzt_int = Integrator::new(21).gain(sim_config.agws.sh24.integrator_gain);
+
+            // FSM OFF-LOAD TO POSITIONER
+            let matfile = MatFile::load("calibrations/sh24/m2_pzt_r.mat")?;
+            let pzt_to_rbm: Vec<Mat<f64>> = (0..7)
+                .map(|i| {
+                    let var: Vec<f64> = matfile.var(format!("var{i}")).unwrap();
+                    let mat = MatRef::from_column_major_slice(&var, 6, 6);
+                    mat.to_owned()
+                })
+                .collect();
+            let pzt_to_rbm = Gain::<f64>::new(pzt_to_rbm);
+            // FSM off-load integrator
+            let pzt_to_rbm_int = Integrator::new(42).gain(sim_config.fsm.offload_integrator_gain);
+
+            // M1 edge sensors to RBMs integrator
+            let m1_es_to_rbm_int =
+                Integrator::new(42).gain(sim_config.m1.edge_sensor.rbm_integrator_gain);
+
+            // On-axis scoring star
+            let atm = AtmosphereBuilder::load("atmosphere/atmosphere.toml")?;
+            let on_axis = if sim_config.atmosphere {
+                OpticalModel::<NoSensor>::builder().atmosphere(atm)
+            } else {
+                OpticalModel::<NoSensor>::builder()
+            }
+            .gmt(Gmt::builder().m1(
+                config::m1::segment::RAW_MODES,
+                config::m1::segment::N_RAW_MODE,
+            ))
+            .sampling_frequency(sim_sampling_frequency as f64)
+            .build()?;
+
+            // Linear Optical Models
+            let m1_lom = LinearOpticalModel::new()?;
+            let m2_lom = LinearOpticalModel::new()?;
+
+            // Mount reconstructor
+            let mount_recon: Reconstructor = serde_pickle::from_reader(
+                File::open("calibrations/mount/recon_sh48-to-mount.pkl")?,
+                Default::default(),
+            )?;
+            println!("SH48 to Mount reconstructor:\n{mount_recon}");
+            provenance.component("sh48_to_mount_recon", &mount_recon);
+
+            // M1 assembly tip-tilt reconstructor
+            let m1_recon: Reconstructor = serde_pickle::from_reader(
+                File::open("calibrations/m1/assembly/recon_sh48-to-m1-assembly.pkl")?,
+                Default::default(),
+            )?;
+            println!("SH48 to Mount reconstructor:\n{m1_recon}");
+            provenance.component("sh48_to_m1_assembly_recon", &m1_recon);
+
+            println!("Model built in {}s", now.elapsed().as_secs());
+            provenance.timing("model build", now.elapsed());
+
+            // SCOPES
+            let shub = OnAxisScopes::new()?;
+            let mount_scopes = MountScopes::new()?;
+
+            let m1_scopes = M1Scopes::new()?;
+            let m2_scopes = M2Scopes::new()?;
+            // ---
+
+            // PERTURBATIONS
+            let time_base = TimeBase::new(n_sim, sim_sampling_frequency as f64);
+            let mount_cmd = scenario.mount(time_base)?;
+            let m1_rbm = scenario.m1_rbm(time_base)?;
+            // the M2 RBM and M1 BM perturbations are written at the simulation rate while bootstrapping
+            let bootstrap_time_base = TimeBase::new(n_bootstrapping, sim_sampling_frequency as f64);
+            let m2_rbm = scenario.m2_rbm(bootstrap_time_base)?;
+            let adder = Operator::new("+");
+            let m2_adder = Operator::<f64>::new("+");
+            // Bootstrapping the FEM and associated controls
+            // let fem = state_space;
+
+            let matfile =
+                MatFile::load("calibrations/m1/modes/20230530_1756_m1_mode_to_force.mat")?;
+            let b2f: Vec<Mat<f64>> = (1..=7)
+                .map(|i| matfile.var(format!("B2F_{i}")).unwrap())
+                .collect();
+            println!(
+                "B2F: {:?}",
+                b2f.iter().map(|x| x.shape()).collect::<Vec<_>>()
+            );
+            let m1_bm_2_forces = Gain::<f64>::new(
+                b2f.iter()
+                    .map(|x| x.subcols(0, sim_config.m1.n_mode).to_owned())
+                    .collect::<Vec<_>>(),
+            );
+            let m1_bm = scenario.m1_modes(sim_config.m1.n_mode, bootstrap_time_base)?;
+            let m1_bms = M1BendingModes::new("calibrations/m1/modes/m1_singular_modes.pkl")?;
+
+            let timer: Timer = Timer::new(n_bootstrapping);
+            let now = Instant::now();
+            actorscript! {
+                #[model(name=bootstrap)]
+            1: timer[Tick] -> {servos::GmtFem}
+
+            // 1: {cfd_loads::M1}[CFDM1WindLoads] -> {servos::GmtFem}
+            // 1: {cfd_loads::M2}[CFDM2WindLoads] -> {servos::GmtFem}
+            // 1: {cfd_loads::Mount}[CFDMountWindLoads] -> {servos::GmtFem}
+
+            1: mount_cmd[MountSetPoint] -> {servos::GmtMount}
+            1: m1_rbm[M1RigidBodyMotions] -> {servos::GmtM1}
+            1: m2_rbm[M2RigidBodyMotions] -> {servos::GmtM2Hex}
+            1: m1_bm[M1ModeShapes] -> m1_bm_2_forces[M1ActuatorCommandForces] -> {servos::GmtM1}
+            1: {servos::GmtFem}[M1State] -> m1_bms[M1State] -> on_axis
+
+            // 1: {servos::GmtFem}[M1RigidBodyMotions] -> on_axis
+            1: {servos::GmtFem}[M2State] -> on_axis
+            1: {servos::GmtFem}[M1RigidBodyMotions] -> m1_lom
+            1: {servos::GmtFem}[M2RigidBodyMotions] -> m2_lom
+            1: m1_lom[M1SegmentPiston].. -> m1_scopes
+            1: m2_lom[M2SegmentPiston].. -> m2_scopes
+            1: m1_lom[M1SegmentTipTilt].. -> m1_scopes
+            1: m2_lom[M2SegmentTipTilt].. -> m2_scopes
+
+            // 1: {servos::GmtFem}[M1EdgeSensors]
+
+            1: on_axis[WfeRms<-9>].. -> shub
+            1: on_axis[SegmentWfeRms<-9>].. -> shub
+            1: on_axis[Mas<TipTilt>].. -> shub
+            1: on_axis[Mas<SegmentTipTilt>].. -> shub
+            1: on_axis[SegmentPiston<-9>] -> shub
+            1000: on_axis[Wavefront].. -> on_axis_wavefront
+            }
+            provenance.timing("bootstrap", now.elapsed());
+
+            if bootstrap_only {
+                shub.lock().await.close().await?;
+                (&mut *mount_scopes.lock().await).await?;
+                m1_scopes.lock().await.close().await?;
+                m2_scopes.lock().await.close().await?;
+                return Ok(());
+            }
+
+            // the M2 RBM and M1 BM perturbations are written at the SH48 rate in closed loop
+            let closed_loop_time_base =
+                TimeBase::new(n_sim - n_bootstrapping, sim_sampling_frequency as f64)
+                    .start(sim_config.bootstrapping_duration as f64)
+                    .decimate(sim_config.agws.sh48.rate);
+            let m2_rbm = scenario.m2_rbm(closed_loop_time_base)?;
+            let m1_bm = scenario.m1_modes(sim_config.m1.n_mode, closed_loop_time_base)?;
+
+            // M2 RBM SH48 calibration
+            let sh48_m2_rbm_recon: Reconstructor = serde_pickle::from_reader(
+                File::open("calibrations/sh48/open_loop_recon_sh48-to-m2-rbm.pkl")?,
+                Default::default(),
+            )?;
+            println!("SH48 to M2 RBM reconstructor:\n{sh48_m2_rbm_recon}");
+            provenance.component("sh48_to_m2_rbm_recon", &sh48_m2_rbm_recon);
+            // the commands are read at the SH48 rate, i.e. one command per SH48 frame,
+            // and the SH48 frame is integrated over the command of the previous frame
+            // let pol = PseudoOpenLoop::<M2RigidBodyMotions>::new(sh48_m2_rbm_recon)
+            //     .integration(1)
+            //     .latency(1);
+            // M1 RBM SH48 calibration
+            let sh48_m1_rbm_recon: Reconstructor = serde_pickle::from_reader(
+                File::open("calibrations/sh48/open_loop_recon_sh48-to-m1-rxy.pkl")?,
+                Default::default(),
+            )?;
+            println!("SH48 to M1 RBM reconstructor:\n{sh48_m1_rbm_recon}");
+            provenance.component("sh48_to_m1_rbm_recon", &sh48_m1_rbm_recon);
+            // let s1 = Sampler::default();
+            let s2 = Sampler::default();
+
+            // M1 BM SH48 calibration
+            let sh48_m1_bm_recon: Reconstructor<_, ClosedLoopCalib> = serde_pickle::from_reader(
+                File::open("calibrations/sh48/closed_loop_recon_sh48-to-m1-bm.pkl")?,
+                Default::default(),
+            )?;
+            let m1_bm_adder = Operator::<f64>::new("+");
+            // M1 bending modes of the 7 segments
+            let n_m1_mode = 7 * sim_config.m1.n_mode;
+            let sh48_int = Integrator::new(n_m1_mode).gain(sim_config.agws.sh48.integrator_gain);
+
+            // let sh48_m2_rbm_recon: Reconstructor<_, ClosedLoopCalib> = serde_pickle::from_reader(
+            //     File::open("calibrations/sh48/closed_loop_recon_sh48-to-m2-rbm.pkl")?,
+            //     Default::default(),
+            // )?;
+            println!("SH48 M2 RBM\n{sh48_m1_rbm_recon}");
+            let mut sh48_m2_rbm_m1_bm_recon: MergeReconstructor<_, M2RigidBodyMotions, M1Modes> =
+                MergeReconstructor::builder()
+                    .calibration("calibrations/sh48/closed_loop_recon_sh48-to-m2-rbm.pkl")
+                    .calibration("calibrations/sh48/closed_loop_recon_sh48-to-m1-bm.pkl")
+                    .build_cached(SH48_MERGE_RECON)?;
+            provenance.input(SH48_MERGE_RECON);
+            if let Some(path) = &sim_config.agws.sh48.reconstructor_watch {
+                sh48_m2_rbm_m1_bm_recon.watch(path);
+                provenance.input(path);
+            }
+            // sh48_m2_rbm_recon.truncated_pseudoinverse(vec![1   // sh48_m2_r
+            // bm_rrecon.truncated_p
+            // seudoinverse(vec![1, 1, 1, 1, 1, 1, 0]);
+            println!("CLOSED LOOP SH48 M2 RBM & M1 BM {sh48_m2_rbm_m1_bm_recon}");
+            anyhow::ensure!(
+                sh48_m2_rbm_m1_bm_recon.estimate_sizes().get(1) == Some(&n_m1_mode),
+                "the SH48 reconstructor M1 bending modes estimate has size {:?} instead of {n_m1_mode} (7x{} modes)",
+                sh48_m2_rbm_m1_bm_recon.estimate_sizes().get(1),
+                sim_config.m1.n_mode
+            );
+            provenance.component("sh48_to_m2_rbm_m1_bm_recon", &sh48_m2_rbm_m1_bm_recon);
+            let m2_rbm_adder = Operator::<f64>::new("+");
+
+            let lpf = LowPassFilter::new(42, 2e-3);
+
+            // let print = Print::<Vec<f64>>::new(8);
+            // let timer: Timer = Timer::new(6001n_sim
+            type AgwsSh48 = Sh48<$sh48>;
+            type AgwsSh24 = Sh24<$sh24>;
+            type AgwsSh24Kernel = Kernel<Sh24<$sh24>>;
+            type AgwsSh48Kernel = Kernel<Sh48<$sh48>>;
+            let now = Instant::now();
+            actorscript! {
+                // #[model(state=running)]
+            #[model(name=closed_loop)]
+            #[labels(on_axis = "GMT Optics & Atmosphere\nw/ On-Axis Star",
+                 mount_cmd="Mount Set-Point",
+                  m1_rbm="M1 RBM",
+                  m2_rbm="M2 RBM",
+                 m1_bm="M1 BM",
+                 sh48_m2_rbm_m1_bm_recon="SH48\nM2 RBM & M1 BM\nReconstructor",
+                 m1_bm_2_forces="Mode to Force",
+                 sh24_recon="SH24\nReconstructor",
+                 fsm_pzt_int="FSM\nIntegrator",
+                 // pzt_to_rbm="FSM\nto\nPositioner",
+                 pzt_to_rbm_int="Positioner\nIntegrator",
+                 m1_es_to_rbm_int="M1 RBM\nIntegrator",
+                 adder="Adder",
+                 m2_adder="Adder",
+                 // m2_rbm_adder="Substracter",
+                 m1_bm_adder="Adder",s2="1:1000",
+                 sh48_int="M1 BM\nIntegrator"
+                 )]
+            // 1: timer[Tick] -> {servos::GmtFem}
+
+            // 1: {cfd_loads::M1}[C10_DM1WindLoads] -AgwsSh48Kernel> SensorDa${cfd_loads::M2}[CFDM2WindLoads] -> {servos::GmtFem}
+            // 1: {cfd_loads::Mount}[CFDMountWindLoads] -> {servos::GmtFem}
+
+            1: mount_cmd[MountSetPoint] -> {servos::GmtMount}
+            1: m1_rbm[Left<M1RigidBodyMotions>] -> adder[M1RigidBodyMotions] -> {servos::GmtM1}
+            $sh48: m2_rbm[Left<M2RigidBodyMotions>] -> m2_adder
+            $sh48: m1_bm[Left<M1ModeShapes>] -> m1_bm_adder[M1ModeShapes]  -> m1_bm_2_forces
+            1: m1_bm_2_forces[M1ActuatorCommandForces] -> {servos::GmtM1}
+            1: {servos::GmtFem}[M1State]
+                -> m1_bms[M1State] -> on_axis
+            1000:  m1_bms[M1State] -> agws_wss
+            1000:  m1_bms[M1ModeResidualRms]$dollar{7}
+            1:  m1_bms[M1State] -> {agws::AgwsSh48}
+            1:  m1_bms[M1State] -> {agws::AgwsSh24}
+
+            1: {servos::GmtFem}[Mas<AverageMountEncoders>] -> mount_scopes
+
+            // FSM to positionner off-load
+            // 1: {servos::GmtFem}[M2FSMPiezoNodes]
+                // -> pzt_to_rbm[Left<M2RigidBodyMotions>] //-> scope_fsm_cmd
+                // 5000: m2_rbm_adder[M2RigidBodyMotions]${42}
+                    $sh48: pzt_to_rbm_int[Right<M2RigidBodyMotions>]
+                    -> m2_adder[M2RigidBodyMotions]$dollar{42}
+                       // -> lpf
+            1: m2_adder[M2RigidBodyMotions] -> {servos::GmtM2Hex}
+            1: {servos::GmtFem}[M2PositionerNodes]
+
+            // M1 edge sensor to RBMs feedback loop
+            1: {servos::GmtFem}[M1EdgeSensors]!
+                -> m1_es_to_rbm_int[Right<M1RigidBodyMotions>]
+                    -> adder
+
+            // FEM state transfer to optical model
+            // 1: {servos::GmtFem}[M1RigidBodyMotions] -> {agws::AgwsSh48}
+            1: {servos::GmtFem}[M2State] -> {agws::AgwsSh48}
+            // 1: {servos::GmtFem}[M1RigidBodyMotions] -> {agws::AgwsSh24}
+            1: {servos::GmtFem}[M2State] -> {agws::AgwsSh24}
+            // 1: {servos::GmtFem}[M1RigidBodyMotions] -> on_axis
+            1: {servos::GmtFem}[M2State] -> on_axis
+            // 1: {servos::GmtFem}[M1State] -> s1
+            // 1000: s1[M1State] -> agws_wss
+            1: {servos::GmtFem}[M2State] -> s2
+            1000: s2[M2State] -> agws_wss[Wavefront] -> agws_wavefronts
+
+            1: {servos::GmtFem}[M1RigidBodyMotions] -> m1_lom
+            1: {servos::GmtFem}[M2RigidBodyMotions] -> m2_lom
+            1: m1_lom[M1SegmentPiston].. -> m1_scopes
+            1: m2_lom[M2SegmentPiston].. -> m2_scopes
+            1: m1_lom[M1SegmentTipTilt].. -> m1_scopes
+            1: m2_lom[M2SegmentTipTilt].. -> m2_scopes
+
+            // // AGWS SH24 to FSMS feedback loop
+            $sh24: {agws::AgwsSh24Kernel}[SensorData] -> sh24_recon[M2FSMFsmCommand] -> fsm_pzt_int
+            1: fsm_pzt_int[M2FSMFsmCommand] -> {servos::GmtM2}
+
+            $sh48: {agws::AgwsSh48Kernel}[SensorData] -> sh48_m2_rbm_m1_bm_recon
+            $sh48: sh48_m2_rbm_m1_bm_recon[M2RigidBodyMotions]$dollar{42} -> pzt_to_rbm_int
+                // -> m2_rbm_adder
+            $sh48: sh48_m2_rbm_m1_bm_recon[RejectedSlopes]$dollar{7}
+            $sh48: sh48_m2_rbm_m1_bm_recon[M1Modes]$dollar{n_m1_mode}
+                -> sh48_int[Right<Estimate>] -> m1_bm_adder
+            // 1000: {agws::AgwsSh48Kernel}[SensorData] -> mount_recon[MountEstimate] -> print
+            // // 1000: {agws::AgwsSh48Kernel}[SensorData] -> pol//m1_recon//[Estimate] -> print
+            // 1000: pzt_to_rbm[M2RigidBodyMotions]
+            // //          -> pol[PseudoSensorData] -> mount_recon[Estimate]->print
+            // pseudo open-loop slopes of the M2 RBM and M1 BM commands, summed by chaining,
+            // the commands are sampled at the SH48 rate (see the units of PseudoOpenLoop::integration):
+            // $sh48: {agws::AgwsSh48Kernel}[SensorData] -> m2_pol
+            // $sh48: m2_adder[M2RigidBodyMotions] -> m2_pol[PseudoSensorData] -> m1_pol
+            // $sh48: sh48_int[M1Modes] -> m1_pol[PseudoSensorData] -> sh48_m2_rbm_m1_bm_recon
+
+            // 1: 2_lom[M2SegmentPiston].. -> m2_scopes
+            // 1: m1_lomM1SegmentTipTilt].. -> m1_scopes
+            // 1: m2_lom[M2Sclosed_loop_recon_sh48-to-m1-bm    // // AGWS SH24 to FSMS feedback loop
+
+            // 1000: {agws::AgwsSh48Kernel}[SensorData] -> mount_recon[MountEstimate] -> print
+            //          // -> pol[PseudoSensorData] -> sh48_m1_rbm_recon[Estimate]${42}->print
+            // 100000: {agws::AgwsSh48}[Frame<Host>]! -> sh48_frame
+            // 1000: {agws::AgwsSh24}[Frame<Host>]! -> sh24_frame
+
+            1: on_axis[WfeRms<-9>].. -> shub
+            1: on_axis[SegmentWfeRms<-9>].. -> shub
+            1: on_axis[SegmentPiston<-9>].. -> shub
+            1: on_axis[Mas<TipTilt>].. -> shub
+            1: on_axis[Mas<SegmentTipTilt>].. -> shub
+            1: on_axis[WfeRms<-9>]$dollar
+            1: on_axis[SegmentWfeRms<-9>]$dollar
+            // // 1: on_axis[SegmentPiston<-9>] -> scope_segment_piston
+            1000: on_axis[Wavefront].. -> on_axis_wavefront
+            }
+
+            provenance.timing("closed loop", now.elapsed());
+
+            closed_loop_logging_1
+                .lock()
+                .await
+                .to_parquet(output_dir.join(ON_AXIS_WFE))?;
+            provenance.output(output_dir.join(ON_AXIS_WFE));
+
+            shub.lock().await.close().await?;
+            (&mut *mount_scopes.lock().await).await?;
+            m1_scopes.lock().await.close().await?;
+            m2_scopes.lock().await.close().await?;
+
+            Ok(())
+        }
+    };
+}
 
-    println!("FEM  : {}", env!("FEM_REPO"));
-    println!("MOUNT: {}", env!("MOUNT_MODEL"));
+/// Generates one model per supported (SH48, SH24, M1 actuators) rates
+/// and the [dispatch] function that maps the rates of a [SimConfig] onto one of them
+macro_rules! rates {
+    ($dollar:tt; $($variant:ident: ($sh48:tt, $sh24:tt, $m1:tt)),* $(,)?) => {
+        $(
+            mod $variant {
+                use super::*;
+                model!($dollar, $sh48, $sh24, $m1);
+            }
+        )*
+
+        /// Supported (SH48, SH24, M1 actuators) rates
+        pub const SUPPORTED_RATES: &[(usize, usize, usize)] = &[$(($sh48, $sh24, $m1)),*];
+
+        /// Runs the model matching the configuration rates
+        async fn dispatch(
+            sim_config: &SimConfig,
+            scenario: &Scenario,
+            output_dir: &Path,
+            bootstrap_only: bool,
+            provenance: &mut Provenance,
+        ) -> anyhow::Result<()> {
+            match (
+                sim_config.agws.sh48.rate,
+                sim_config.agws.sh24.rate,
+                sim_config.m1.actuator_rate,
+            ) {
+                $(($sh48, $sh24, $m1) => $variant::run(sim_config, scenario, output_dir, bootstrap_only, provenance).await,)*
+                rates => Err(unsupported_rates(rates)),
+            }
+        }
+    };
+}
 
-    let now = Instant::now();
+rates! {$;
+    sh48_1000_sh24_1_m1_10: (1000, 1, 10),
+    sh48_1000_sh24_5_m1_10: (1000, 5, 10),
+    sh48_1000_sh24_10_m1_10: (1000, 10, 10),
+    sh48_2000_sh24_1_m1_10: (2000, 1, 10),
+    sh48_2000_sh24_5_m1_10: (2000, 5, 10),
+    sh48_2000_sh24_10_m1_10: (2000, 10, 10),
+    sh48_5000_sh24_1_m1_10: (5000, 1, 10),
+    sh48_5000_sh24_5_m1_10: (5000, 5, 10),
+    sh48_5000_sh24_10_m1_10: (5000, 10, 10),
+}
+
+/// Checks that the configuration rates are supported
+fn check_rates(sim_config: &SimConfig) -> anyhow::Result<()> {
+    let rates = (
+        sim_config.agws.sh48.rate,
+        sim_config.agws.sh24.rate,
+        sim_config.m1.actuator_rate,
+    );
+    if !SUPPORTED_RATES.contains(&rates) {
+        return Err(unsupported_rates(rates));
+    }
+    Ok(())
+}
+
+fn unsupported_rates((sh48, sh24, m1): (usize, usize, usize)) -> anyhow::Error {
+    anyhow::anyhow!(
+        "unsupported rates combination: SH48 {sh48}, SH24 {sh24}, M1 actuators {m1}, expected one of (SH48, SH24, M1 actuators) {:?}",
+        SUPPORTED_RATES
+    )
+}
 
-    let sim_sampling_frequency = 1000;
-    let sim_duration = 80_usize; // second
-    let bootstrapping_duration = 4_usize; // second
-    let n_bootstrapping = sim_sampling_frequency * bootstrapping_duration;
-    let n_sim = n_bootstrapping + sim_sampling_frequency * sim_duration + 1;
-
-    let fem = FEM::from_env()?;
-    // println!("{fem}");
-
-    // let cfd_loads = Sys::<SigmoidCfdLoads>::try_from(
-    //     CfdLoads::foh(".", sim_sampling_frequency)
-    //         .duration((sim_duration + bootstrapping_duration) as f64)
-    //         .mount(&mut fem, 0, None)
-    //         .m1_segments()
-    //         .m2_segments(),
-    // )?;
-
-    // M1 EDGE SENSORS TO RIGID-BODY MOTIONS TRANSFORM
-    let m1_es_2_rbm: nalgebra::DMatrix<f64> =
-        MatFile::load("calibrations/m1/edge-sensors/es_2_rbm.mat")?.var("m1_r_es")?;
-    // let servos =
-    //     Sys::<GmtServoMechanisms<{ config::m1::ACTUATOR_RATE }, 1>>::from_data_repo_or_else(
-    //         "servos.bin",
-    //         || {
-    //             GmtServoMechanisms::<{ config::m1::ACTUATOR_RATE }, 1>::new(
-    //                 sim_sampling_frequency as f64,
-    //                 fem,
-    //             )
-    //             .m1_segment_figure(M1SegmentFigure::new())
-    //         },
-    //     )?;
-
-    let servos = GmtServoMechanisms::<{ config::m1::segment::ACTUATOR_RATE }, 1>::new(
-        sim_sampling_frequency as f64,
-        fem,
+/// Builds and runs the model
+async fn run(args: &RunArgs, bootstrap_only: bool) -> anyhow::Result<()> {
+    let now = Instant::now();
+    let sim_config = args.sim_config()?;
+    println!("{sim_config}");
+    check_rates(&sim_config)?;
+    let manifest = Manifest::from_toml(&args.config.manifest)?;
+    manifest.preflight(&sim_config)?;
+    std::fs::create_dir_all(&args.output_dir)?;
+    let scenario = Scenario::from_toml(&args.scenario)?;
+
+    let mut provenance =
+        Provenance::new(env!("FEM_REPO"), env!("MOUNT_MODEL"), env!("GIT_REVISION"));
+    provenance
+        .config(&sim_config)
+        .scenario(&scenario)
+        .inputs(&manifest);
+    let result = dispatch(
+        &sim_config,
+        &scenario,
+        &args.output_dir,
+        bootstrap_only,
+        &mut provenance,
     )
-    .edge_sensors(EdgeSensors::m1().m1_with(m1_es_2_rbm))
-    // .wind_loads(WindLoads::new())
-    .m1_segment_figure(M1SegmentFigure::new())
-    .build()?;
-    println!("{servos}");
-    // serde_pickle::to_writer(
-    //     &mut File::create("servos.bin")?,
-    //     &servos,
-    //     Default::default(),
-    // )?;
-    // let rdr = BufReader::new(File::open("servos.bin")?);
-    // let servos :Sys< GmtServoMechanisms<{ config::m1::ACTUATOR_RATE }, 1>>=
-    // serde_pickle::from_reader(rdr, Default::default())?;
-
-    // AGWS
-    let recon: Reconstructor = serde_pickle::from_reader(
-        File::open("calibrations/sh24/recon_sh24-to-pzt_pth.pkl")?,
-        Default::default(),
-    )?;
-    println!("SH24 to FSM reconstructor:\n{recon}");
-    let (agws_wss, mut agws): (
-        _,
-        Sys<Agws<{ config::agws::sh48::RATE }, { config::agws::sh24::RATE }>>,
-    ) = {
-        let agws = if config::ATMOSPHERE {
-            Agws::builder()
-                .load_atmosphere("atmosphere/atmosphere.toml", sim_sampling_frequency as f64)?
-        } else {
-            Agws::builder()
-                .sh24(
-                    ShackHartmannBuilder::sh24()
-                        // .source(AgwsGuideStar::sh24().zenith_azimuth(vec![0f32], vec![0f32]))
-                        .use_calibration_src(),
-                )
-                .sh48(ShackHartmannBuilder::sh48().use_calibration_src())
+    .await;
+    provenance.timing("total", now.elapsed()).end(&result);
+    provenance.to_json(args.output_dir.join(RUN_PROVENANCE))?;
+    result
+}
+
+/// Checks the model inputs without building the model
+fn check(args: &ConfigArgs) -> anyhow::Result<()> {
+    let sim_config = args.sim_config()?;
+    println!("{sim_config}");
+    check_rates(&sim_config)?;
+    let manifest = Manifest::from_toml(&args.manifest)?;
+    println!("Calibration artifacts SHA-256:");
+    for (path, hash) in manifest.hashes() {
+        match hash {
+            Ok(hash) => println!(" * {hash} {path:?}"),
+            Err(e) => println!(" * {e} {path:?}"),
         }
-        .gmt(Gmt::builder().m1(
-            gmt_ns_im::config::m1::segment::RAW_MODES,
-            gmt_ns_im::config::m1::segment::N_RAW_MODE,
-        ))
-        .sh24_calibration(recon);
-        (agws.wave_sensor().build()?, agws.build()?)
-    };
-    if let Some(p24) = config::agws::sh24::POINTING_ERROR {
-        let _ = agws.sh24_pointing(p24).await;
     }
-    println!("{agws}");
-    println!("{agws_wss}");
-
-    // let sh48_frame: gif::Frame<f32> = gif::Frame::new("sh48_frame.png", 48 * 8);
-    // let sh24_frame: gif::Frame<f32> = gif::Frame::new("sh24_frame.png", 24 * 12);
-    // let on_axis_wavefront: gif::Frame<f64> = gif::Frame::new("on-axis_wavefront.png", 512);
-    let agws_wavefronts: gif::Frame<f64> = gif::Frame::new("agws_wavefronts.png", 512);
-    let on_axis_wavefront: gif::Gif<f64> =
-        gif::Gif::new("on-axis_wavefront.gif", 512, 512)?.delay(200);
-
-    // FSM command integrator
-    let fsm_pzt_int = Integrator::new(21).gain(config::agws::sh24::INTEGRATOR_GAIN);
-
-    // FSM OFF-LOAD TO POSITIONER
-    let matfile = MatFile::load("calibrations/sh24/m2_pzt_r.mat")?;
-    let pzt_to_rbm: Vec<Mat<f64>> = (0..7)
-        .map(|i| {
-            let var: Vec<f64> = matfile.var(format!("var{i}")).unwrap();
-            let mat = MatRef::from_column_major_slice(&var, 6, 6);
-            mat.to_owned()
-        })
-        .collect();
-    let pzt_to_rbm = Gain::<f64>::new(pzt_to_rbm);
-    // FSM off-load integrator
-    let pzt_to_rbm_int = Integrator::new(42).gain(config::fsm::OFFLOAD_INTEGRATOR_GAIN);
-
-    // M1 edge sensors to RBMs integrator
-    let m1_es_to_rbm_int = Integrator::new(42).gain(config::m1::edge_sensor::RBM_INTEGRATOR_GAIN);
-
-    // On-axis scoring star
-    let atm = AtmosphereBuilder::load("atmosphere/atmosphere.toml")?;
-    let on_axis = if config::ATMOSPHERE {
-        OpticalModel::<NoSensor>::builder().atmosphere(atm)
-    } else {
-        OpticalModel::<NoSensor>::builder()
+    manifest.preflight(&sim_config)?;
+    println!("Model inputs: OK");
+    Ok(())
+}
+
+/// Statistics of the on-axis wavefront error RMS logged during a run
+fn summarize(args: &SummarizeArgs) -> anyhow::Result<()> {
+    let sim_config = args.config.sim_config()?;
+    let path = args.output_dir.join(RUN_PROVENANCE);
+    if path.exists() {
+        println!("{}", Provenance::from_json(path)?);
     }
-    .gmt(Gmt::builder().m1(
-        config::m1::segment::RAW_MODES,
-        config::m1::segment::N_RAW_MODE,
-    ))
-    .sampling_frequency(sim_sampling_frequency as f64)
-    .build()?;
-
-    // Linear Optical Models
-    let m1_lom = LinearOpticalModel::new()?;
-    let m2_lom = LinearOpticalModel::new()?;
-
-    // Mount reconstructor
-    let mount_recon: Reconstructor = serde_pickle::from_reader(
-        File::open("calibrations/mount/recon_sh48-to-mount.pkl")?,
-        Default::default(),
-    )?;
-    println!("SH48 to Mount reconstructor:\n{mount_recon}");
-
-    // M1 assembly tip-tilt reconstructor
-    let m1_recon: Reconstructor = serde_pickle::from_reader(
-        File::open("calibrations/m1/assembly/recon_sh48-to-m1-assembly.pkl")?,
-        Default::default(),
-    )?;
-    println!("SH48 to Mount reconstructor:\n{m1_recon}");
-
-    println!("Model built in {}s", now.elapsed().as_secs());
-
-    // SCOPES
-    let shub = OnAxisScopes::new()?;
-    let mount_scopes = MountScopes::new()?;
-
-    let m1_scopes = M1Scopes::new()?;
-    let m2_scopes = M2Scopes::new()?;
-    // ---
-
-    // PERTURBATIONS
-    // let m2_rbm = Signals::new(42, 3000 + n_bootstrapping).channel(3, 1e-6);
-    let mount_cmd = Signals::new(3, n_sim);
-    // .channel(0, -1e-5)
-    // .channel(1, 1e-5);
-    let mut m1_rbm = vec![vec![0f64; 6]; 7];
-    // m1_rbm[0][0] = 1e-6;
-    // m1_rbm[6][4] = 1e-6;
-    let m1_rbm = Signals::from((m1_rbm, n_sim));
-    let mut m2_rbm = vec![vec![0f64; 6]; 7];
-    m2_rbm[0][0] = 1e-6;
-    // m2_rbm[6][4] = 1e-6;
-    let m2_rbm = Signals::from((m2_rbm, n_sim));
-    let adder = Operator::new("+");
-    let m2_adder = Operator::<f64>::new("+");
-    // Bootstrapping the FEM and associated controls
-    // let fem = state_space;
-
-    let matfile = MatFile::load("calibrations/m1/modes/20230530_1756_m1_mode_to_force.mat")?;
-    let b2f: Vec<Mat<f64>> = (1..=7)
-        .map(|i| matfile.var(format!("B2F_{i}")).unwrap())
-        .collect();
-    println!(
-        "B2F: {:?}",
-        b2f.iter().map(|x| x.shape()).collect::<Vec<_>>()
-    );
-    let m1_bm_2_forces = Gain::<f64>::new(
-        b2f.iter()
-            .map(|x| x.subcols(0, config::m1::segment::N_MODE).to_owned())
-            .collect::<Vec<_>>(),
-    );
-    let mut m1_bm = vec![vec![0f64; config::m1::segment::N_MODE]; 7];
-    // m1_bm
-    //     .iter_mut()
-    //     .enumerate()
-    //     .take(1)
-    //     .for_each(|(i, b)| b[i] = 1e-6);
-    let m1_bm = Signals::from((m1_bm, n_sim));
-    let m1_bms = M1BendingModes::new("calibrations/m1/modes/m1_singular_modes.pkl")?;
-
-    let timer: Timer = Timer::new(n_bootstrapping);
-    actorscript! {
-        #[model(name=bootstrap)]
-    1: timer[Tick] -> {servos::GmtFem}
-
-    // 1: {cfd_loads::M1}[CFDM1WindLoads] -> {servos::GmtFem}
-    // 1: {cfd_loads::M2}[CFDM2WindLoads] -> {servos::GmtFem}
-    // 1: {cfd_loads::Mount}[CFDMountWindLoads] -> {servos::GmtFem}
-
-    1: mount_cmd[MountSetPoint] -> {servos::GmtMount}
-    1: m1_rbm[M1RigidBodyMotions] -> {servos::GmtM1}
-    1: m2_rbm[M2RigidBodyMotions] -> {servos::GmtM2Hex}
-    1: m1_bm[M1ModeShapes] -> m1_bm_2_forces[M1ActuatorCommandForces] -> {servos::GmtM1}
-    1: {servos::GmtFem}[M1State] -> m1_bms[M1State] -> on_axis
-
-    // 1: {servos::GmtFem}[M1RigidBodyMotions] -> on_axis
-    1: {servos::GmtFem}[M2State] -> on_axis
-    1: {servos::GmtFem}[M1RigidBodyMotions] -> m1_lom
-    1: {servos::GmtFem}[M2RigidBodyMotions] -> m2_lom
-    1: m1_lom[M1SegmentPiston].. -> m1_scopes
-    1: m2_lom[M2SegmentPiston].. -> m2_scopes
-    1: m1_lom[M1SegmentTipTilt].. -> m1_scopes
-    1: m2_lom[M2SegmentTipTilt].. -> m2_scopes
-
-    // 1: {servos::GmtFem}[M1EdgeSensors]
-
-    1: on_axis[WfeRms<-9>].. -> shub
-    1: on_axis[SegmentWfeRms<-9>].. -> shub
-    1: on_axis[Mas<TipTilt>].. -> shub
-    1: on_axis[Mas<SegmentTipTilt>].. -> shub
-    1: on_axis[SegmentPiston<-9>] -> shub
-    1000: on_axis[Wavefront].. -> on_axis_wavefront
+    let path = args.output_dir.join(ON_AXIS_WFE);
+    let mut logs = Arrow::from_parquet(&path)?;
+    println!("On-axis WFE RMS [nm] ({path:?}):");
+    for name in ["WfeRms<-9>", "SegmentWfeRms<-9>"] {
+        let data: Vec<Vec<f64>> = logs.iter(name)?.collect();
+        let n = args
+            .last
+            .map(|last| (last * sim_config.sampling_frequency as f64).round() as usize)
+            .unwrap_or(data.len())
+            .clamp(1, data.len().max(1));
+        let window = &data[data.len().saturating_sub(n)..];
+        let Some(n_item) = window.first().map(|x| x.len()) else {
+            println!(" * {name}: no data");
+            continue;
+        };
+        println!(" * {name} (last {} samples):", window.len());
+        for i in 0..n_item {
+            let x: Vec<f64> = window.iter().map(|x| x[i]).collect();
+            let mean = x.iter().sum::<f64>() / x.len() as f64;
+            let std = (x.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / x.len() as f64).sqrt();
+            let max = x.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            println!(
+                "   #{:<2}: mean {mean:8.1}, std {std:8.1}, max {max:8.1}, last {:8.1}",
+                i + 1,
+                x[x.len() - 1]
+            );
+        }
     }
+    Ok(())
+}
 
-    // M2 RBM SH48 calibration
-    let sh48_m2_rbm_recon: Reconstructor = serde_pickle::from_reader(
-        File::open("calibrations/sh48/open_loop_recon_sh48-to-m2-rbm.pkl")?,
-        Default::default(),
-    )?;
-    println!("SH48 to M2 RBM reconstructor:\n{sh48_m2_rbm_recon}");
-    // let pol = PseudoOpenLoop::new(sh48_m2_rbm_recon);
-    // M1 RBM SH48 calibration
-    let sh48_m1_rbm_recon: Reconstructor = serde_pickle::from_reader(
-        File::open("calibrations/sh48/open_loop_recon_sh48-to-m1-rxy.pkl")?,
-        Default::default(),
-    )?;
-    println!("SH48 to M1 RBM reconstructor:\n{sh48_m1_rbm_recon}");
-    // let s1 = Sampler::default();
-    let s2 = Sampler::default();
-
-    // M1 BM SH48 calibration
-    let sh48_m1_bm_recon: Reconstructor<_, ClosedLoopCalib> = serde_pickle::from_reader(
-        File::open("calibrations/sh48/closed_loop_recon_sh48-to-m1-bm.pkl")?,
-        Default::default(),
-    )?;
-    let m1_bm_adder = Operator::<f64>::new("+");
-    let sh48_int = Integrator::new(27 * 7).gain(0.1);
-
-    // let sh48_m2_rbm_recon: Reconstructor<_, ClosedLoopCalib> = serde_pickle::from_reader(
-    //     File::open("calibrations/sh48/closed_loop_recon_sh48-to-m2-rbm.pkl")?,
-    //     Default::default(),
-    // )?;
-    println!("SH48 M2 RBM\n{sh48_m1_rbm_recon}");
-    let mut sh48_m2_rbm_m1_bm_recon = MergeReconstructor::new(
-        "calibrations/sh48/closed_loop_recon_sh48-to-m2-rbm.pkl",
-        "calibrations/sh48/closed_loop_recon_sh48-to-m1-bm.pkl",
-        None,
-    )?;
-    // sh48_m2_rbm_recon.truncated_pseudoinverse(vec![1   // sh48_m2_r
-    // bm_rrecon.truncated_p
-    // seudoinverse(vec![1, 1, 1, 1, 1, 1, 0]);
-    println!("CLOSED LOOP SH48 M2 RBM & M1 BM {sh48_m2_rbm_m1_bm_recon}");
-    let m2_rbm_adder = Operator::<f64>::new("+");
-
-    let lpf = LowPassFilter::new(42, 2e-3);
-
-    // let print = Print::<Vec<f64>>::new(8);
-    // let timer: Timer = Timer::new(6001n_sim
-    type AgwsSh48 = Sh48<{ config::agws::sh48::RATE }>;
-    type AgwsSh24 = Sh24<{ config::agws::sh24::RATE }>;
-    type AgwsSh24Kernel = Kernel<Sh24<{ config::agws::sh24::RATE }>>;
-    type AgwsSh48Kernel = Kernel<Sh48<{ config::agws::sh48::RATE }>>;
-    actorscript! {
-        // #[model(state=running)]
-    #[labels(on_axis = "GMT Optics & Atmosphere\nw/ On-Axis Star",
-         mount_cmd="Mount Set-Point",
-          m1_rbm="M1 RBM",
-          m2_rbm="M2 RBM",
-         m1_bm="M1 BM",
-         sh48_m2_rbm_m1_bm_recon="SH48\nM2 RBM & M1 BM\nReconstructor",
-         m1_bm_2_forces="Mode to Force",
-         fsm_pzt_int="FSM\nIntegrator",
-         // pzt_to_rbm="FSM\nto\nPositioner",
-         pzt_to_rbm_int="Positioner\nIntegrator",
-         m1_es_to_rbm_int="M1 RBM\nIntegrator",
-         adder="Adder",
-         m2_adder="Adder",
-         // m2_rbm_adder="Substracter",
-         m1_bm_adder="Adder",s2="1:1000",
-         sh48_int="M1 BM\nIntegrator"
-         )]
-    // 1: timer[Tick] -> {servos::GmtFem}
-
-    // 1: {cfd_loads::M1}[C10_DM1WindLoads] -AgwsSh48Kernel> SensorDa${cfd_loads::M2}[CFDM2WindLoads] -> {servos::GmtFem}
-    // 1: {cfd_loads::Mount}[CFDMountWindLoads] -> {servos::GmtFem}
-
-    1: mount_cmd[MountSetPoint] -> {servos::GmtMount}
-    1: m1_rbm[Left<M1RigidBodyMotions>] -> adder[M1RigidBodyMotions] -> {servos::GmtM1}
-    5000: m2_rbm[Left<M2RigidBodyMotions>] -> m2_adder
-    5000: m1_bm[Left<M1ModeShapes>] -> m1_bm_adder[M1ModeShapes]  -> m1_bm_2_forces
-    1: m1_bm_2_forces[M1ActuatorCommandForces] -> {servos::GmtM1}
-    1: {servos::GmtFem}[M1State]
-        -> m1_bms[M1State] -> on_axis
-    1000:  m1_bms[M1State] -> agws_wss
-    1:  m1_bms[M1State] -> {agws::AgwsSh48}
-    1:  m1_bms[M1State] -> {agws::AgwsSh24}
-
-    1: {servos::GmtFem}[Mas<AverageMountEncoders>] -> mount_scopes
-
-    // FSM to positionner off-load
-    // 1: {servos::GmtFem}[M2FSMPiezoNodes]
-        // -> pzt_to_rbm[Left<M2RigidBodyMotions>] //-> scope_fsm_cmd
-        // 5000: m2_rbm_adder[M2RigidBodyMotions]${42}
-            5000: pzt_to_rbm_int[Right<M2RigidBodyMotions>]
-            -> m2_adder[M2RigidBodyMotions]${42}
-               // -> lpf
-    1: m2_adder[M2RigidBodyMotions] -> {servos::GmtM2Hex}
-    1: {servos::GmtFem}[M2PositionerNodes]
-
-    // M1 edge sensor to RBMs feedback loop
-    1: {servos::GmtFem}[M1EdgeSensors]!
-        -> m1_es_to_rbm_int[Right<M1RigidBodyMotions>]
-            -> adder
-
-    // FEM state transfer to optical model
-    // 1: {servos::GmtFem}[M1RigidBodyMotions] -> {agws::AgwsSh48}
-    1: {servos::GmtFem}[M2State] -> {agws::AgwsSh48}
-    // 1: {servos::GmtFem}[M1RigidBodyMotions] -> {agws::AgwsSh24}
-    1: {servos::GmtFem}[M2State] -> {agws::AgwsSh24}
-    // 1: {servos::GmtFem}[M1RigidBodyMotions] -> on_axis
-    1: {servos::GmtFem}[M2State] -> on_axis
-    // 1: {servos::GmtFem}[M1State] -> s1
-    // 1000: s1[M1State] -> agws_wss
-    1: {servos::GmtFem}[M2State] -> s2
-    1000: s2[M2State] -> agws_wss[Wavefront] -> agws_wavefronts
-
-    1: {servos::GmtFem}[M1RigidBodyMotions] -> m1_lom
-    1: {servos::GmtFem}[M2RigidBodyMotions] -> m2_lom
-    1: m1_lom[M1SegmentPiston].. -> m1_scopes
-    1: m2_lom[M2SegmentPiston].. -> m2_scopes
-    1: m1_lom[M1SegmentTipTilt].. -> m1_scopes
-    1: m2_lom[M2SegmentTipTilt].. -> m2_scopes
-
-    // // AGWS SH24 to FSMS feedback loop
-    5: {agws::AgwsSh24Kernel}[M2FSMFsmCommand] -> fsm_pzt_int
-    1: fsm_pzt_int[M2FSMFsmCommand] -> {servos::GmtM2}
-
-    5000: {agws::AgwsSh48Kernel}[SensorData] -> sh48_m2_rbm_m1_bm_recon
-    5000: sh48_m2_rbm_m1_bm_recon[SplitEstimate<0>]${42} -> pzt_to_rbm_int
-        // -> m2_rbm_adder
-    5000: sh48_m2_rbm_m1_bm_recon[SplitEstimate<1>]${27*7}
-        -> sh48_int[Right<Estimate>] -> m1_bm_adder
-    // 1000: {agws::AgwsSh48Kernel}[SensorData] -> mount_recon[MountEstimate] -> print
-    // // 1000: {agws::AgwsSh48Kernel}[SensorData] -> pol//m1_recon//[Estimate] -> print
-    // 1000: pzt_to_rbm[M2RigidBodyMotions]
-    // //          -> pol[PseudoSensorData] -> mount_recon[Estimate]->print
-
-    // 1: 2_lom[M2SegmentPiston].. -> m2_scopes
-    // 1: m1_lomM1SegmentTipTilt].. -> m1_scopes
-    // 1: m2_lom[M2Sclosed_loop_recon_sh48-to-m1-bm    // // AGWS SH24 to FSMS feedback loop
-
-    // 1000: {agws::AgwsSh48Kernel}[SensorData] -> mount_recon[MountEstimate] -> print
-    //          // -> pol[PseudoSensorData] -> sh48_m1_rbm_recon[Estimate]${42}->print
-    // 100000: {agws::AgwsSh48}[Frame<Host>]! -> sh48_frame
-    // 1000: {agws::AgwsSh24}[Frame<Host>]! -> sh24_frame
-
-    1: on_axis[WfeRms<-9>].. -> shub
-    1: on_axis[SegmentWfeRms<-9>].. -> shub
-    1: on_axis[SegmentPiston<-9>].. -> shub
-    1: on_axis[Mas<TipTilt>].. -> shub
-    1: on_axis[Mas<SegmentTipTilt>].. -> shub
-    // // 1: on_axis[SegmentPiston<-9>] -> scope_segment_piston
-    1000: on_axis[Wavefront].. -> on_axis_wavefront
-    }
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
 
-    shub.lock().await.close().await?;
-    (&mut *mount_scopes.lock().await).await?;
-    m1_scopes.lock().await.close().await?;
-    m2_scopes.lock().await.close().await?;
+    let cli = Cli::parse();
 
-    Ok(())
+    println!("FEM  : {}", env!("FEM_REPO"));
+    println!("MOUNT: {}", env!("MOUNT_MODEL"));
+
+    match cli.command {
+        Command::Run(args) => run(&args, false).await,
+        Command::BootstrapOnly(args) => run(&args, true).await,
+        Command::Check(args) => check(&args),
+        Command::Summarize(args) => summarize(&args),
+    }
 }
@@ -0,0 +1,517 @@
+/*!
+# Calibration data manifest
+
+The manifest (`calibrations/manifest.toml`) lists every calibration artifact loaded by the model
+together with the crate or the script that produces it, its SHA-256 hash and its expected content.
+[Manifest::preflight] checks all the artifacts before the FEM is loaded and reports every failure at once.
+
+```toml
+[[artifact]]
+path = "calibrations/m1/modes/20230530_1756_m1_mode_to_force.mat"
+producer = "calibrations/m1/modes/m1_fem_bending_modes.py"
+kind = "mat"
+sha256 = "..."
+[[artifact.variables]]
+name = "B2F_{i}"
+indices = [1, 2, 3, 4, 5, 6, 7]
+min_cols = "n_mode"
+```
+In a variable name, `{i}` is replaced by each of the `indices`.
+A bound is either a number or `"n_mode"`, the number of M1 bending modes of the [SimConfig].
+*/
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use faer::Mat;
+use gmt_dos_clients_crseo::calibration::{
+    CalibrationMode, ClosedLoopCalib, Reconstructor, algebra::CalibProps,
+};
+use gmt_dos_systems_m1::SingularModes;
+use matio_rs::MatFile;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::SimConfig;
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Open(io::Error),
+    Toml(toml::de::Error),
+    Preflight(Vec<ArtifactError>),
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestError::Open(error) => error.fmt(f),
+            ManifestError::Toml(error) => error.fmt(f),
+            ManifestError::Preflight(errors) => {
+                writeln!(
+                    f,
+                    "calibration preflight failed with {} error(s):",
+                    errors.len()
+                )?;
+                for error in errors {
+                    writeln!(f, " * {error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl Error for ManifestError {}
+impl From<io::Error> for ManifestError {
+    fn from(value: io::Error) -> Self {
+        Self::Open(value)
+    }
+}
+impl From<toml::de::Error> for ManifestError {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Toml(value)
+    }
+}
+
+/// Calibration artifact check failure
+#[derive(Debug)]
+pub enum ArtifactError {
+    Missing(PathBuf),
+    Read {
+        path: PathBuf,
+        error: String,
+    },
+    Stale {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+    Unhashed {
+        path: PathBuf,
+        found: String,
+    },
+    Segments {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+    Shape {
+        path: PathBuf,
+        name: String,
+        expected: String,
+        found: (usize, usize),
+    },
+}
+
+impl Display for ArtifactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtifactError::Missing(path) => write!(f, "{path:?}: missing"),
+            ArtifactError::Read { path, error } => write!(f, "{path:?}: failed to read ({error})"),
+            ArtifactError::Stale {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{path:?}: stale, expected SHA-256 {expected}, found {found}"
+            ),
+            ArtifactError::Unhashed { path, found } => write!(
+                f,
+                "{path:?}: no SHA-256 hash in the manifest, add `sha256 = \"{found}\"`"
+            ),
+            ArtifactError::Segments {
+                path,
+                expected,
+                found,
+            } => write!(f, "{path:?}: expected {expected} segments, found {found}"),
+            ArtifactError::Shape {
+                path,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{path:?}: `{name}` expected shape {expected}, found {found:?}"
+            ),
+        }
+    }
+}
+
+/// Parameter a bound refers to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Param {
+    #[serde(rename = "n_mode")]
+    NMode,
+}
+
+/// Dimension bound
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Bound {
+    Value(usize),
+    Param(Param),
+}
+impl Bound {
+    fn value(&self, sim_config: &SimConfig) -> usize {
+        match self {
+            Bound::Value(n) => *n,
+            Bound::Param(Param::NMode) => sim_config.m1.n_mode,
+        }
+    }
+}
+
+/// Artifact content type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// any file
+    File,
+    /// Matlab file
+    Mat,
+    /// pickled open-loop `Reconstructor`
+    Reconstructor,
+    /// pickled closed-loop `Reconstructor`
+    ClosedLoopReconstructor,
+    /// pickled M1 `SingularModes`
+    SingularModes,
+}
+
+/// Matlab file variable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variable {
+    /// variable name, `{i}` is replaced by each of the `indices`
+    pub name: String,
+    #[serde(default)]
+    pub indices: Vec<usize>,
+    pub rows: Option<Bound>,
+    pub cols: Option<Bound>,
+    pub min_cols: Option<Bound>,
+}
+impl Variable {
+    /// Returns the variable names
+    pub fn names(&self) -> Vec<String> {
+        if self.indices.is_empty() {
+            vec![self.name.clone()]
+        } else {
+            self.indices
+                .iter()
+                .map(|i| self.name.replace("{i}", &i.to_string()))
+                .collect()
+        }
+    }
+    fn expected(&self, sim_config: &SimConfig) -> String {
+        let bound =
+            |b: &Option<Bound>| b.map_or("*".to_string(), |b| b.value(sim_config).to_string());
+        match self.min_cols {
+            Some(min_cols) => format!("[{},>={}]", bound(&self.rows), min_cols.value(sim_config)),
+            None => format!("[{},{}]", bound(&self.rows), bound(&self.cols)),
+        }
+    }
+    fn check(&self, (nrows, ncols): (usize, usize), sim_config: &SimConfig) -> bool {
+        self.rows.is_none_or(|n| n.value(sim_config) == nrows)
+            && self.cols.is_none_or(|n| n.value(sim_config) == ncols)
+            && self.min_cols.is_none_or(|n| ncols >= n.value(sim_config))
+    }
+}
+
+/// Calibration artifact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// path relative to the root of the workspace
+    pub path: PathBuf,
+    /// crate or script that produces the artifact
+    pub producer: String,
+    pub kind: ArtifactKind,
+    /// SHA-256 hash of the file, an artifact without a hash fails the check
+    pub sha256: Option<String>,
+    /// number of segments of a reconstructor or of singular modes
+    pub n_segment: Option<usize>,
+    /// number of columns of each segment calibration matrix of a reconstructor
+    pub n_cols: Option<Bound>,
+    /// Matlab file variables
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+    /// written by the model when it is missing, e.g. a cache: it may be missing and it is not hashed
+    #[serde(default)]
+    pub generated: bool,
+}
+
+impl Artifact {
+    /// Checks the artifact against its description
+    pub fn check(&self, sim_config: &SimConfig) -> Vec<ArtifactError> {
+        let path = self.path.clone();
+        if !path.exists() {
+            return if self.generated {
+                vec![]
+            } else {
+                vec![ArtifactError::Missing(path)]
+            };
+        }
+        let mut errors = vec![];
+        match (self.sha256.as_ref(), sha256(&path)) {
+            (Some(expected), Ok(found)) if *expected != found => {
+                errors.push(ArtifactError::Stale {
+                    path: path.clone(),
+                    expected: expected.clone(),
+                    found,
+                })
+            }
+            (None, Ok(found)) if !self.generated => errors.push(ArtifactError::Unhashed {
+                path: path.clone(),
+                found,
+            }),
+            (_, Err(e)) => {
+                return vec![ArtifactError::Read {
+                    path,
+                    error: e.to_string(),
+                }];
+            }
+            _ => (),
+        }
+        let read_error = |error: String| ArtifactError::Read {
+            path: path.clone(),
+            error,
+        };
+        match self.kind {
+            ArtifactKind::File => (),
+            ArtifactKind::Mat => match MatFile::load(&path) {
+                Ok(mat) => {
+                    for var in &self.variables {
+                        for name in var.names() {
+                            if var.rows.is_none() && var.cols.is_none() && var.min_cols.is_none() {
+                                let data: Result<Vec<f64>, _> = mat.var(name.clone());
+                                if let Err(e) = data {
+                                    errors.push(read_error(format!("`{name}`: {e}")));
+                                }
+                                continue;
+                            }
+                            let data: Result<Mat<f64>, _> = mat.var(name.clone());
+                            match data {
+                                Ok(m) if var.check(m.shape(), sim_config) => (),
+                                Ok(m) => errors.push(ArtifactError::Shape {
+                                    path: path.clone(),
+                                    name,
+                                    expected: var.expected(sim_config),
+                                    found: m.shape(),
+                                }),
+                                Err(e) => errors.push(read_error(format!("`{name}`: {e}"))),
+                            }
+                        }
+                    }
+                }
+                Err(e) => errors.push(read_error(e.to_string())),
+            },
+            ArtifactKind::Reconstructor => {
+                match File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        serde_pickle::from_reader::<_, Reconstructor>(file, Default::default())
+                            .map_err(|e| e.to_string())
+                    }) {
+                    Ok(mut recon) => {
+                        let n_cols: Vec<_> =
+                            recon.calib_slice_mut().iter().map(|c| c.n_cols()).collect();
+                        errors.extend(self.check_segments(&n_cols, sim_config));
+                    }
+                    Err(e) => errors.push(read_error(e)),
+                }
+            }
+            ArtifactKind::ClosedLoopReconstructor => {
+                match File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        serde_pickle::from_reader::<
+                            _,
+                            Reconstructor<CalibrationMode, ClosedLoopCalib>,
+                        >(file, Default::default())
+                        .map_err(|e| e.to_string())
+                    }) {
+                    Ok(mut recon) => {
+                        let n_cols: Vec<_> =
+                            recon.calib_slice_mut().iter().map(|c| c.n_cols()).collect();
+                        errors.extend(self.check_segments(&n_cols, sim_config));
+                    }
+                    Err(e) => errors.push(read_error(e)),
+                }
+            }
+            ArtifactKind::SingularModes => {
+                match File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        serde_pickle::from_reader::<_, Vec<SingularModes>>(file, Default::default())
+                            .map_err(|e| e.to_string())
+                    }) {
+                    Ok(modes) => {
+                        let n_cols: Vec<_> = modes.iter().map(|m| m.mat_ref().ncols()).collect();
+                        errors.extend(self.check_segments(&n_cols, sim_config));
+                    }
+                    Err(e) => errors.push(read_error(e)),
+                }
+            }
+        }
+        errors
+    }
+    fn check_segments(&self, n_cols: &[usize], sim_config: &SimConfig) -> Vec<ArtifactError> {
+        let mut errors = vec![];
+        if let Some(n_segment) = self.n_segment {
+            if n_segment != n_cols.len() {
+                errors.push(ArtifactError::Segments {
+                    path: self.path.clone(),
+                    expected: n_segment,
+                    found: n_cols.len(),
+                });
+            }
+        }
+        if let Some(n) = self.n_cols.map(|n| n.value(sim_config)) {
+            errors.extend(
+                n_cols
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &nc)| nc != n)
+                    .map(|(i, &nc)| ArtifactError::Shape {
+                        path: self.path.clone(),
+                        name: format!("segment #{}", i + 1),
+                        expected: format!("[*,{n}]"),
+                        found: (0, nc),
+                    }),
+            );
+        }
+        errors
+    }
+}
+
+/// Calibration data manifest
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(rename = "artifact")]
+    pub artifacts: Vec<Artifact>,
+}
+
+impl Manifest {
+    /// Loads the manifest from a TOML file
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let manifest: Self = toml::from_str(&fs::read_to_string(path.as_ref())?)?;
+        Ok(manifest)
+    }
+    /// Checks all the artifacts, returning all the failures
+    pub fn preflight(&self, sim_config: &SimConfig) -> Result<(), ManifestError> {
+        let errors: Vec<_> = self
+            .artifacts
+            .iter()
+            .flat_map(|artifact| artifact.check(sim_config))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ManifestError::Preflight(errors))
+        }
+    }
+    /// Returns the path and the SHA-256 hash of the artifacts
+    pub fn hashes(&self) -> Vec<(&Path, io::Result<String>)> {
+        self.artifacts
+            .iter()
+            .map(|artifact| (artifact.path.as_path(), sha256(&artifact.path)))
+            .collect()
+    }
+}
+
+/// SHA-256 hash of a file as an hexadecimal string
+pub fn sha256(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path.as_ref())?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use gmt_dos_clients_crseo::calibration::Calib;
+
+    use super::*;
+
+    fn artifact(path: PathBuf, kind: ArtifactKind) -> Artifact {
+        Artifact {
+            path,
+            producer: "test".into(),
+            kind,
+            sha256: None,
+            n_segment: None,
+            n_cols: None,
+            variables: vec![],
+            generated: false,
+        }
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = std::env::temp_dir().join("gmt-ns-im_manifest_missing.pkl");
+        let mut artifact = artifact(path, ArtifactKind::File);
+        let errors = artifact.check(&SimConfig::default());
+        assert!(matches!(errors[..], [ArtifactError::Missing(_)]));
+        artifact.generated = true;
+        assert!(artifact.check(&SimConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let path = std::env::temp_dir().join("gmt-ns-im_manifest_hash.txt");
+        fs::write(&path, "calibration").unwrap();
+        let mut artifact = artifact(path.clone(), ArtifactKind::File);
+        let errors = artifact.check(&SimConfig::default());
+        let hash = match &errors[..] {
+            [ArtifactError::Unhashed { found, .. }] => found.clone(),
+            _ => panic!("expected an unhashed artifact, found {errors:?}"),
+        };
+        artifact.sha256 = Some(hash);
+        assert!(artifact.check(&SimConfig::default()).is_empty());
+        fs::write(&path, "modified calibration").unwrap();
+        let errors = artifact.check(&SimConfig::default());
+        assert!(matches!(errors[..], [ArtifactError::Stale { .. }]));
+    }
+
+    #[test]
+    fn shape_mismatch() {
+        let n_slope = 10;
+        let calib = Calib::builder()
+            .c(vec![1f64; n_slope * 3])
+            .n_cols(3)
+            .mask(vec![true; n_slope])
+            .mode(CalibrationMode::modes(3, 1e-6))
+            .build();
+        let recon = Reconstructor::new(vec![calib]);
+        let path = std::env::temp_dir().join("gmt-ns-im_manifest_shape.pkl");
+        serde_pickle::to_writer(
+            &mut File::create(&path).unwrap(),
+            &recon,
+            Default::default(),
+        )
+        .unwrap();
+        let mut artifact = artifact(path.clone(), ArtifactKind::Reconstructor);
+        artifact.sha256 = Some(sha256(&path).unwrap());
+        artifact.n_segment = Some(7);
+        artifact.n_cols = Some(Bound::Value(2));
+        let errors = artifact.check(&SimConfig::default());
+        assert!(
+            matches!(
+                errors[..],
+                [
+                    ArtifactError::Segments {
+                        expected: 7,
+                        found: 1,
+                        ..
+                    },
+                    ArtifactError::Shape { found: (0, 3), .. }
+                ]
+            ),
+            "{errors:?}"
+        );
+    }
+}
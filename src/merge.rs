@@ -1,20 +1,97 @@
 use std::{
-    any::type_name, error::Error, fmt::Display, fs::File, io, marker::PhantomData, path::Path,
+    any::type_name,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io, iter,
+    marker::PhantomData,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
-use faer::MatRef;
+use faer::{Mat, MatRef};
 use gmt_dos_clients_crseo::calibration::{
-    Calib, CalibrationMode, ClosedLoopCalib, Modality, Reconstructor, SegmentMode,
-    algebra::CalibProps,
+    Calib, CalibrationMode, ClosedLoopCalib, Modality, Reconstructor, algebra::CalibProps,
 };
-use gmt_dos_clients_io::{gmt_m2::M2RigidBodyMotions, optics::M1Modes};
+use gmt_dos_clients_io::{gmt_m1::M1RigidBodyMotions, gmt_m2::M2RigidBodyMotions, optics::M1Modes};
 use interface::{Data, OperatorLeftRight, Read, UID, UniqueIdentifier, Update, Write};
+use serde::{Deserialize, Serialize};
+
+use crate::noise_propagation::NoisePropagation;
+
+mod bad_slopes;
+mod diagnostics;
+mod hot_swap;
+mod inversion;
+mod kernel;
+mod mode_mask;
+mod persistence;
+pub use bad_slopes::{BadSlopes, RejectedSlopes};
+pub use diagnostics::{CrossTalk, MergeDiagnostics, SegmentDiagnostics};
+pub use hot_swap::ReconstructorMatrix;
+pub(crate) use hot_swap::Watch;
+pub use inversion::{NoiseCovariance, Regularization};
+pub use mode_mask::{MaskedModes, ModeMask};
 
 #[derive(Debug)]
 pub enum MergeError {
     Open(io::Error),
     Pickle(serde_pickle::Error),
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    /// the file is not a saved [MergeReconstructor]
+    Format(PathBuf),
+    /// the calibrations do not have the same number of segments
+    SegmentCount {
+        a: PathBuf,
+        b: PathBuf,
+        a_segments: usize,
+        b_segments: usize,
+    },
+    /// the calibrations of a segment do not have the same number of rows
+    RowCount {
+        segment: usize,
+        a: PathBuf,
+        b: PathBuf,
+        a_rows: usize,
+        b_rows: usize,
+    },
+    /// the calibrations of a segment do not have the same mask
+    Mask {
+        segment: usize,
+        a: PathBuf,
+        b: PathBuf,
+    },
+    /// the merged calibration of a segment is rank deficient
+    RankDeficiency {
+        segment: usize,
+        paths: Vec<PathBuf>,
+        rank: usize,
+        n_cols: usize,
+    },
+    /// the slope noise covariance of a segment does not match the number of valid slopes
+    NoiseCovariance {
+        segment: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// the mode mask does not match the size of the merged estimate
+    ModeMask {
+        expected: usize,
+        found: usize,
+    },
+    /// the replacement pseudo-inverse of a segment does not match the size of its calibration
+    ReconstructorMatrix {
+        segment: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// the replacement reconstructor does not have the same estimate sizes
+    EstimateSizes {
+        path: PathBuf,
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
 }
 
 impl Display for MergeError {
@@ -22,6 +99,70 @@ impl Display for MergeError {
         match self {
             MergeError::Open(error) => error.fmt(f),
             MergeError::Pickle(error) => error.fmt(f),
+            MergeError::Json(error) => error.fmt(f),
+            MergeError::Bincode(error) => error.fmt(f),
+            MergeError::Format(path) => write!(f, "{path:?} is not a saved merge reconstructor"),
+            MergeError::SegmentCount {
+                a,
+                b,
+                a_segments,
+                b_segments,
+            } => write!(
+                f,
+                "{a:?} has {a_segments} segments but {b:?} has {b_segments} segments"
+            ),
+            MergeError::RowCount {
+                segment,
+                a,
+                b,
+                a_rows,
+                b_rows,
+            } => write!(
+                f,
+                "segment #{segment}: {a:?} has {a_rows} rows but {b:?} has {b_rows} rows"
+            ),
+            MergeError::Mask { segment, a, b } => {
+                write!(
+                    f,
+                    "segment #{segment}: {a:?} and {b:?} have different masks"
+                )
+            }
+            MergeError::RankDeficiency {
+                segment,
+                paths,
+                rank,
+                n_cols,
+            } => write!(
+                f,
+                "segment #{segment}: the merged calibration of {paths:?} is rank deficient (rank {rank} for {n_cols} columns)"
+            ),
+            MergeError::NoiseCovariance {
+                segment,
+                expected,
+                found,
+            } => write!(
+                f,
+                "segment #{segment}: expected a slope noise covariance of size {expected}, found {found}"
+            ),
+            MergeError::ModeMask { expected, found } => {
+                write!(f, "expected a mode mask of size {expected}, found {found}")
+            }
+            MergeError::ReconstructorMatrix {
+                segment,
+                expected,
+                found,
+            } => write!(
+                f,
+                "segment #{segment}: expected a reconstructor matrix of size {expected}, found {found}"
+            ),
+            MergeError::EstimateSizes {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{path:?} has estimates of sizes {found:?} instead of {expected:?}"
+            ),
         }
     }
 }
@@ -36,207 +177,503 @@ impl From<serde_pickle::Error> for MergeError {
         Self::Pickle(value)
     }
 }
+impl From<serde_json::Error> for MergeError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+impl From<bincode::Error> for MergeError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Bincode(value)
+    }
+}
 
-pub struct MergeReconstructor<M: Modality + Display, A, B, C = ()> {
+/// Reconstructor merging several closed-loop calibrations
+///
+/// The calibration matrices of each segment are concatenated column-wise
+/// into a single matrix that is jointly inverted.
+/// The estimate of each calibration (block) is written with [SplitEstimate]`<I>`,
+/// `I` being the index of the calibration in the order it was given to the builder.
+///
+/// `A` and `B` are the types of the first 2 blocks,
+/// their estimates are also written as `A` and `B`
+/// and the concatenation of all the estimates as [MergedEstimate].
+///
+/// A [MergeReconstructor] can be saved and reloaded (see [MergeReconstructor::save])
+/// together with the hashes of its source calibrations and its inversion settings.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Reconstructor<M, Calib<M>>: Serialize",
+    deserialize = "Reconstructor<M, Calib<M>>: Deserialize<'de>"
+))]
+pub struct MergeReconstructor<M: Modality + Display, A = (), B = ()> {
     recon: Reconstructor<M, Calib<M>>,
+    #[serde(skip)]
     data: Arc<Vec<f64>>,
-    estimates: (Arc<Vec<f64>>, Arc<Vec<f64>>, Arc<Vec<f64>>),
-    // estimate_sizes: Vec<(usize, usize, usize)>,
+    // calibration mode and number of columns of each block for each segment
+    blocks: Vec<Vec<(CalibrationMode, usize)>>,
+    #[serde(skip)]
+    estimates: Vec<Arc<Vec<f64>>>,
+    estimate_sizes: Vec<usize>,
+    calibrations: Vec<PathBuf>,
+    // SHA-256 hashes of the calibrations
+    sha256: Vec<String>,
+    svd_truncation: Option<Vec<usize>>,
+    regularization: Option<Regularization>,
+    // slope noise covariance
+    noise: Option<NoiseCovariance>,
+    // normalization factor of each block for each segment
+    norms: Vec<Vec<f64>>,
+    #[serde(skip)]
+    mode_mask: Option<Vec<bool>>,
+    #[serde(skip)]
+    masked_modes: MaskedModes,
+    // pseudo-inverses without the columns of the masked modes
+    #[serde(skip)]
+    masked_pinv: Option<Vec<Mat<f64>>>,
+    #[serde(skip)]
+    bad_slopes: BadSlopes,
+    #[serde(skip)]
+    slope_threshold: Option<f64>,
+    // reconstruction kernel of each segment
+    #[serde(skip)]
+    kernels: Vec<kernel::SegmentKernel>,
+    #[serde(skip)]
+    rejected_slopes: Vec<usize>,
+    // replacement pseudo-inverses applied at the next update
+    #[serde(skip)]
+    pending_matrix: Option<Arc<Vec<Vec<f64>>>>,
+    // saved reconstructor reloaded whenever it is modified
+    #[serde(skip)]
+    watch: Option<Watch>,
+    #[serde(skip)]
     a: PhantomData<A>,
-
+    #[serde(skip)]
     b: PhantomData<B>,
-    c: PhantomData<C>,
 }
-impl<M: Modality + Display + Default, A, B, C> Display for MergeReconstructor<M, A, B, C> {
+impl<M: Modality + Display + Default, A, B> Display for MergeReconstructor<M, A, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(
             f,
-            "Merge Reconstructor [{},{},{}]:",
+            "Merge Reconstructor [{},{}] with {} blocks of sizes {:?}:",
             type_name::<A>(),
             type_name::<B>(),
-            type_name::<C>()
+            self.n_block(),
+            self.estimate_sizes
         )?;
         self.recon.fmt(f)?;
         Ok(())
     }
 }
 
-impl MergeReconstructor<SegmentMode, M2RigidBodyMotions, M1Modes> {
-    pub fn new(
-        a: impl AsRef<Path>,
-        b: impl AsRef<Path>,
-        svd_truncation: Option<Vec<usize>>,
-    ) -> Result<Self, MergeError> {
-        let file = File::open(a.as_ref())?;
-        let mut recon_a: Reconstructor<CalibrationMode, ClosedLoopCalib> =
-            serde_pickle::from_reader(&file, Default::default())?;
-        let file = File::open(b.as_ref())?;
-        let mut recon_b: Reconstructor<CalibrationMode, ClosedLoopCalib> =
-            serde_pickle::from_reader(&file, Default::default())?;
-        let ((calibs, sizes), nrms): ((Vec<_>, Vec<_>), Vec<_>) = recon_a
-            .calib_slice_mut()
-            .iter_mut()
-            .zip(recon_b.calib_slice_mut())
-            .map(|(ca, cb)| {
-                assert_eq!(ca.n_rows(), cb.n_rows());
+/// [MergeReconstructor] builder
+#[derive(Debug, Clone)]
+pub struct MergeReconstructorBuilder<A, B> {
+    calibrations: Vec<PathBuf>,
+    svd_truncation: Option<Vec<usize>>,
+    regularization: Option<Regularization>,
+    noise: Option<NoiseCovariance>,
+    a: PhantomData<A>,
+    b: PhantomData<B>,
+}
+
+impl<A, B> Default for MergeReconstructorBuilder<A, B> {
+    fn default() -> Self {
+        Self {
+            calibrations: Default::default(),
+            svd_truncation: Default::default(),
+            regularization: Default::default(),
+            noise: Default::default(),
+            a: PhantomData,
+            b: PhantomData,
+        }
+    }
+}
 
-                let ca_nrm = ca.normalize();
-                let cb_nrm = cb.normalize();
+impl<A, B> MergeReconstructorBuilder<A, B> {
+    /// Appends a calibration pickle file
+    ///
+    /// The pickle is either a closed-loop or an open-loop reconstructor
+    pub fn calibration(mut self, path: impl AsRef<Path>) -> Self {
+        self.calibrations.push(path.as_ref().to_path_buf());
+        self
+    }
+    /// Appends several calibration pickle files
+    pub fn calibrations<P: AsRef<Path>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.calibrations
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+    /// Sets the number of singular values discarded for each segment
+    pub fn svd_truncation(mut self, svd_truncation: Vec<usize>) -> Self {
+        self.svd_truncation = Some(svd_truncation);
+        self
+    }
+    /// Inverts the calibrations with a Tikhonov regularization
+    ///
+    /// The SVD truncation is ignored
+    pub fn regularization(mut self, regularization: Regularization) -> Self {
+        self.regularization = Some(regularization);
+        self
+    }
+    /// Inverts the calibrations with the minimum-variance estimator for the given slope noise
+    ///
+    /// The regularization weights are then the inverse of the standard deviations of the modes
+    /// and the SVD truncation is ignored.
+    /// Without a [Regularization], i.e. without the standard deviations of the modes,
+    /// the estimator is the plain weighted least-squares estimator
+    /// and a [NoiseCovariance::Scalar] gives the same inverse as no noise covariance
+    pub fn noise_covariance(mut self, noise: NoiseCovariance) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+    fn is_regularized(&self) -> bool {
+        self.regularization.is_some() || self.noise.is_some()
+    }
+    /// Loads, merges and inverts the calibrations
+    pub fn build(self) -> Result<MergeReconstructor<CalibrationMode, A, B>, MergeError> {
+        self.build_with(true)
+    }
+    /// Loads, merges and inverts the calibrations without checking their rank
+    ///
+    /// The pseudo-inverses of rank deficient calibrations are meaningless,
+    /// use it only to investigate their conditioning (see [MergeReconstructor::diagnostics])
+    pub fn build_unchecked(self) -> Result<MergeReconstructor<CalibrationMode, A, B>, MergeError> {
+        self.build_with(false)
+    }
+    fn build_with(
+        self,
+        check_rank: bool,
+    ) -> Result<MergeReconstructor<CalibrationMode, A, B>, MergeError> {
+        let mut calib_slices = self
+            .calibrations
+            .iter()
+            .map(load_calibration)
+            .collect::<Result<Vec<_>, MergeError>>()?;
+        let sha256 = self
+            .calibrations
+            .iter()
+            .map(crate::manifest::sha256)
+            .collect::<io::Result<Vec<_>>>()?;
+        let paths = &self.calibrations;
+        let n_segment = calib_slices.first().map_or(0, |c| c.len());
+        for (path, c) in paths.iter().zip(&calib_slices).skip(1) {
+            if c.len() != n_segment {
+                return Err(MergeError::SegmentCount {
+                    a: paths[0].clone(),
+                    b: path.clone(),
+                    a_segments: n_segment,
+                    b_segments: c.len(),
+                });
+            }
+        }
+
+        let mut calibs = vec![];
+        let mut inverses = vec![];
+        let mut blocks = vec![];
+        let mut sizes = vec![];
+        let mut nrms = vec![];
+        for i in 0..n_segment {
+            let ca = &calib_slices[0][i];
+            for (path, c) in paths.iter().zip(&calib_slices).skip(1) {
+                let c = &c[i];
+                if c.n_rows() != ca.n_rows() {
+                    return Err(MergeError::RowCount {
+                        segment: i + 1,
+                        a: paths[0].clone(),
+                        b: path.clone(),
+                        a_rows: ca.n_rows(),
+                        b_rows: c.n_rows(),
+                    });
+                }
+                if c.mask_as_slice() != ca.mask_as_slice() {
+                    return Err(MergeError::Mask {
+                        segment: i + 1,
+                        a: paths[0].clone(),
+                        b: path.clone(),
+                    });
+                }
+            }
+            let n_rows = ca.n_rows();
+            let mask = ca.mask_as_slice().to_vec();
+
+            let mut mat = vec![];
+            let mut segment_blocks = vec![];
+            let mut segment_sizes = vec![];
+            let mut segment_nrms = vec![];
+            for c in calib_slices.iter_mut().map(|c| &mut c[i]) {
+                segment_nrms.push(c.normalize());
+                segment_sizes.push(c.n_cols());
+                segment_blocks.push((c.mode(), c.n_cols()));
+                mat.extend(c.as_slice());
+            }
+            let n_cols: usize = segment_sizes.iter().sum();
 
-                let na = ca.n_cols();
-                let nb = cb.n_cols();
+            // the singular values that are discarded do not count toward the rank deficiency
+            let n_truncated = self
+                .svd_truncation
+                .as_ref()
+                .and_then(|n| n.get(i).cloned())
+                .unwrap_or_default();
+            let rank = rank(&mat, n_rows, n_cols);
+            if check_rank && !self.is_regularized() && rank + n_truncated < n_cols {
+                return Err(MergeError::RankDeficiency {
+                    segment: i + 1,
+                    paths: rank_deficient_blocks(&mat, n_rows, &segment_sizes)
+                        .into_iter()
+                        .map(|k| paths[k].clone())
+                        .collect(),
+                    rank,
+                    n_cols,
+                });
+            }
 
-                let mut mat = ca.as_slice().to_vec();
-                mat.extend(cb.as_slice());
-                let calib = Calib::builder()
+            if self.is_regularized() {
+                inverses.push(inversion::regularized(
+                    i,
+                    MatRef::from_column_major_slice(&mat, n_rows, n_cols),
+                    &segment_sizes,
+                    self.regularization.as_ref(),
+                    self.noise.as_ref(),
+                )?);
+            }
+            calibs.push(
+                Calib::builder()
                     .c(mat)
-                    .n_cols(nb + na)
-                    .mask(ca.mask_as_slice().to_vec())
-                    .mode(SegmentMode::new(ca.mode(), cb.mode()))
-                    .build();
-                ((calib, (na, nb, 0)), (ca_nrm, cb_nrm))
-            })
-            .unzip();
+                    .n_cols(n_cols)
+                    .mask(mask)
+                    .mode(CalibrationMode::None)
+                    .build(),
+            );
+            blocks.push(segment_blocks);
+            sizes.push(segment_sizes);
+            nrms.push(segment_nrms);
+        }
+
         let mut recon = Reconstructor::new(calibs);
-        if let Some(n) = svd_truncation {
+        if let Some(n) = self
+            .svd_truncation
+            .clone()
+            .filter(|_| !self.is_regularized())
+        {
             recon.truncated_pseudoinverse(n)
         } else {
             recon.pseudoinverse()
         };
+        if self.is_regularized() {
+            recon
+                .pinv()
+                .zip(&inverses)
+                .for_each(|(p, inverse)| p.transform(|_| inverse.clone()));
+        }
+        // undoing the normalization of each block
         recon
             .pinv()
             .zip(&sizes)
             .zip(&nrms)
-            .for_each(|((p, &(na, nb, _nc)), &(ca_nrm, cb_nrm))| {
-                let mut l = faer::mat::Mat::<f64>::identity(nb + na, nb + na);
+            .for_each(|((p, sizes), nrms)| {
+                let n: usize = sizes.iter().sum();
+                let mut l = faer::mat::Mat::<f64>::identity(n, n);
                 l.diagonal_mut()
                     .column_vector_mut()
                     .iter_mut()
-                    .take(na)
-                    .for_each(|x| *x /= ca_nrm);
-                l.diagonal_mut()
-                    .column_vector_mut()
-                    .iter_mut()
-                    .skip(na)
-                    .for_each(|x| *x /= cb_nrm);
+                    .zip(
+                        sizes
+                            .iter()
+                            .zip(nrms)
+                            .flat_map(|(&size, &nrm)| iter::repeat_n(nrm, size)),
+                    )
+                    .for_each(|(x, nrm)| *x /= nrm);
                 p.transform(|x| &l * x);
             });
-        Ok(Self {
+
+        let n_block = self.calibrations.len();
+        let estimate_sizes = (0..n_block)
+            .map(|k| {
+                blocks
+                    .iter()
+                    .map(|blocks| {
+                        let (mode, n) = &blocks[k];
+                        mode.fill(vec![0f64; *n].into_iter()).len()
+                    })
+                    .sum()
+            })
+            .collect::<Vec<usize>>();
+        Ok(MergeReconstructor {
             recon,
+            data: Default::default(),
+            blocks,
+            estimates: estimate_sizes
+                .iter()
+                .map(|&n| Arc::new(vec![0f64; n]))
+                .collect(),
+            estimate_sizes,
+            noise: self.noise,
+            calibrations: self.calibrations,
+            sha256,
+            svd_truncation: self.svd_truncation,
+            regularization: self.regularization,
+            norms: nrms,
+            mode_mask: None,
+            masked_modes: Default::default(),
+            masked_pinv: None,
+            bad_slopes: Default::default(),
+            slope_threshold: None,
+            kernels: vec![],
+            rejected_slopes: vec![],
+            pending_matrix: None,
+            watch: None,
             a: PhantomData,
             b: PhantomData,
-            c: PhantomData,
-            data: Default::default(),
-            estimates: Default::default(),
-            // estimate_sizes: sizes,
         })
     }
 }
-impl MergeReconstructor<CalibrationMode, M1Modes, ()> {
-    pub fn single(a: impl AsRef<Path>) -> Result<Self, MergeError> {
-        let file = File::open(a.as_ref())?;
-        let mut recon_a: Reconstructor<CalibrationMode, ClosedLoopCalib> =
-            serde_pickle::from_reader(&file, Default::default())?;
-        let (calibs, _sizes): (Vec<_>, Vec<_>) = recon_a
+
+/// Loads the per-segment calibrations of either a closed-loop or an open-loop reconstructor pickle
+fn load_calibration(path: impl AsRef<Path>) -> Result<Vec<Calib<CalibrationMode>>, MergeError> {
+    let closed_loop: Result<Reconstructor<CalibrationMode, ClosedLoopCalib>, _> =
+        serde_pickle::from_reader(File::open(path.as_ref())?, Default::default());
+    if let Ok(mut recon) = closed_loop {
+        return Ok(recon
             .calib_slice_mut()
-            .iter_mut()
-            .map(|ca| {
-                // let ca_nrm = ca.normalize();
-
-                let na = ca.n_cols();
-                // let mut l = faer::mat::Mat::<f64>::identity(na, na);
-                // l.diagonal_mut()
-                //     .column_vector_mut()
-                //     .iter_mut()
-                //     .for_each(|x| *x /= ca_nrm);
-
-                let mat = ca.as_slice().to_vec();
-                let calib = Calib::builder()
-                    .c(mat)
-                    .n_cols(na)
-                    .mask(ca.mask_as_slice().to_vec())
-                    .mode(CalibrationMode::None)
-                    .build();
-                // calib.transform(|x| &l * x);
-                (calib, (na, 0, 0))
+            .iter()
+            .map(|c| {
+                Calib::builder()
+                    .c(c.as_slice().to_vec())
+                    .n_cols(c.n_cols())
+                    .mask(c.mask_as_slice().to_vec())
+                    .mode(c.mode())
+                    .build()
             })
-            .unzip();
-        let mut recon = Reconstructor::new(calibs);
-        let nrms = recon.normalize();
-        recon.pseudoinverse();
-        recon.pinv().zip(&nrms).for_each(|(p, n)| {
-            p.transform(|x| x / *n);
-        });
-        Ok(Self {
-            recon,
-            a: PhantomData,
-            b: PhantomData,
-            c: PhantomData,
-            data: Default::default(),
-            estimates: Default::default(),
-            // estimate_sizes: sizes,
+            .collect());
+    }
+    let mut recon: Reconstructor<CalibrationMode, Calib<CalibrationMode>> =
+        serde_pickle::from_reader(File::open(path.as_ref())?, Default::default())?;
+    Ok(recon.calib_slice_mut().to_vec())
+}
+
+/// Singular values of a column-major matrix in decreasing order
+///
+/// Returns an empty vector if the SVD fails
+fn singular_values(mat: &[f64], n_rows: usize, n_cols: usize) -> Vec<f64> {
+    MatRef::from_column_major_slice(mat, n_rows, n_cols)
+        .thin_svd()
+        .map(|svd| svd.S().column_vector().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Numerical rank of a column-major matrix
+fn rank(mat: &[f64], n_rows: usize, n_cols: usize) -> usize {
+    let s = singular_values(mat, n_rows, n_cols);
+    let s_max = s.iter().cloned().fold(0f64, f64::max);
+    let tol = s_max * n_rows.max(n_cols) as f64 * f64::EPSILON;
+    s.iter().filter(|&&x| x > tol).count()
+}
+
+/// Indices of the blocks responsible for the rank deficiency of a merged calibration
+///
+/// Returns the first rank deficient block or pair of blocks, or all the blocks if none is found
+fn rank_deficient_blocks(mat: &[f64], n_rows: usize, sizes: &[usize]) -> Vec<usize> {
+    let offsets: Vec<usize> = sizes
+        .iter()
+        .scan(0, |o, &n| {
+            *o += n;
+            Some(*o - n)
         })
+        .collect();
+    let block = |k: usize| &mat[offsets[k] * n_rows..(offsets[k] + sizes[k]) * n_rows];
+    if let Some(k) = (0..sizes.len()).find(|&k| rank(block(k), n_rows, sizes[k]) < sizes[k]) {
+        return vec![k];
     }
+    for j in 0..sizes.len() {
+        for k in j + 1..sizes.len() {
+            let pair: Vec<f64> = block(j).iter().chain(block(k)).cloned().collect();
+            let n_cols = sizes[j] + sizes[k];
+            if rank(&pair, n_rows, n_cols) < n_cols {
+                return vec![j, k];
+            }
+        }
+    }
+    (0..sizes.len()).collect()
 }
 
-impl<A, B> Update for MergeReconstructor<SegmentMode, A, B>
-where
-    A: UniqueIdentifier,
-    B: UniqueIdentifier,
-{
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Creates a [MergeReconstructor] builder
+    pub fn builder() -> MergeReconstructorBuilder<A, B> {
+        Default::default()
+    }
+    /// Number of merged calibrations
+    pub fn n_block(&self) -> usize {
+        self.estimate_sizes.len()
+    }
+    /// Size of the estimate of each merged calibration
+    pub fn estimate_sizes(&self) -> &[usize] {
+        &self.estimate_sizes
+    }
+    /// Noise propagation coefficients of each mode of each segment
+    ///
+    /// The modes are the calibrated modes of the merged calibration of each segment,
+    /// in the order of the blocks
+    pub fn noise_propagation(&mut self) -> NoisePropagation {
+        let blocks: Vec<Vec<usize>> = self
+            .blocks
+            .iter()
+            .map(|blocks| blocks.iter().map(|(_, n)| *n).collect())
+            .collect();
+        let pinvs: Vec<Mat<f64>> = match &self.masked_pinv {
+            Some(pinvs) => pinvs.clone(),
+            None => self
+                .recon
+                .calib_pinv()
+                .map(|(_, ic)| ic.as_ref().to_owned())
+                .collect(),
+        };
+        NoisePropagation::new(pinvs.iter().map(|pinv| pinv.as_ref()), Some(&blocks))
+    }
+}
+
+impl MergeReconstructor<CalibrationMode, M2RigidBodyMotions, M1Modes> {
+    /// Merges the M2 RBM and M1 bending modes closed-loop calibrations
+    pub fn new(
+        a: impl AsRef<Path>,
+        b: impl AsRef<Path>,
+        svd_truncation: Option<Vec<usize>>,
+    ) -> Result<Self, MergeError> {
+        let builder = Self::builder().calibration(a).calibration(b);
+        if let Some(n) = svd_truncation {
+            builder.svd_truncation(n)
+        } else {
+            builder
+        }
+        .build()
+    }
+}
+impl MergeReconstructor<CalibrationMode, M1Modes, ()> {
+    /// Reconstructor of a single calibration of the M1 bending modes
+    ///
+    /// Reads the [SensorData](gmt_dos_clients_io::optics::SensorData) and writes [M1Modes]
+    pub fn single(a: impl AsRef<Path>) -> Result<Self, MergeError> {
+        Self::builder().calibration(a).build()
+    }
+}
+
+impl<A, B> Update for MergeReconstructor<CalibrationMode, A, B> {
     fn update(&mut self) {
-        // dbg!(self.data.len());
-        // let mut u = 0;
-        // let (ya, yb): (Vec<_>, Vec<_>) = self
-        //     .recon
-        //     .iter()
-        //     .zip(&self.estimate_sizes)
-        //     .map(|(r, (na, _nb, _nc))| {
-        //         let m = r.mat_ref();
-        //         let n = m.ncols();
-        //         let s = &self.data[u..u + n];
-        //         u += n;
-        //         dbg!(u);
-        //         let y = m * MatRef::from_column_major_slice(s, n, 1);
-        //         let (ya, yb) = y.col_as_slice(0).split_at(*na);
-        //         (ya.to_vec(), yb.to_vec())
-        //     })
-        //     .unzip();
-        // dbg!(yb.iter().map(|x| { x.len() }).collect::<Vec<_>>());
-        let (ya, yb): (Vec<_>, Vec<_>) = self
-            .recon
-            .calib_pinv()
-            .map(|(c, ic)| {
-                let rhs = c.mask(&self.data);
-                let y = ic * MatRef::from_column_major_slice(rhs.as_slice(), rhs.len(), 1);
-                c.mode().fill_split(y.col_as_slice(0).to_vec().into_iter())
-            })
-            // .zip(&self.estimate_sizes)
-            // .map(|(y, (na, nb, nc))| {
-            //     dbg!(y.len());
-            //     let (ya, yb) = y.split_at(*na);
-            //     (ya.to_vec(), yb.to_vec())
-            // })
-            .unzip();
-        self.estimates = (
-            Arc::new(ya.into_iter().flatten().collect()),
-            Arc::new(yb.into_iter().flatten().collect()),
-            Default::default(),
-        )
+        self.hot_swap();
+        self.reconstruct();
     }
 }
 
-impl<U, A, B> Read<U> for MergeReconstructor<SegmentMode, A, B>
+impl<U, A, B> Read<U> for MergeReconstructor<CalibrationMode, A, B>
 where
     U: UniqueIdentifier<DataType = Vec<f64>>,
-    A: UniqueIdentifier,
-    B: UniqueIdentifier,
 {
     fn read(&mut self, data: Data<U>) {
         self.data = data.into_arc();
     }
 }
 
+/// Estimate of the `I`th calibration of a [MergeReconstructor]
 #[derive(UID)]
 pub enum SplitEstimate<const I: usize> {}
 
@@ -244,42 +681,68 @@ impl<const I: usize> OperatorLeftRight for SplitEstimate<I> {
     const LEFT: bool = true;
 }
 
-impl<A, B, const I: usize> Write<SplitEstimate<I>> for MergeReconstructor<SegmentMode, A, B>
-where
-    A: UniqueIdentifier,
-    B: UniqueIdentifier,
-{
+impl<A, B, const I: usize> Write<SplitEstimate<I>> for MergeReconstructor<CalibrationMode, A, B> {
     fn write(&mut self) -> Option<Data<SplitEstimate<I>>> {
+        self.estimates
+            .get(I)
+            .map(|estimate| estimate.clone().into())
+    }
+}
+
+/// Concatenation of the estimates of all the calibrations of a [MergeReconstructor]
+#[derive(UID)]
+pub enum MergedEstimate {}
+
+impl<A, B> Write<MergedEstimate> for MergeReconstructor<CalibrationMode, A, B> {
+    fn write(&mut self) -> Option<Data<MergedEstimate>> {
         Some(
-            match I {
-                0 => self.estimates.0.clone(),
-                1 => self.estimates.1.clone(),
-                _ => panic!("Found SplitEstimate #{I}, expected 0 or 1"),
-            }
-            .into(),
+            self.estimates
+                .iter()
+                .flat_map(|estimate| estimate.iter().cloned())
+                .collect::<Vec<f64>>()
+                .into(),
         )
     }
 }
-// impl<B> Write<M2RigidBodyMotions> for MergeReconstructor<M2RigidBodyMotions, B>
-// where
-//     B: UniqueIdentifier,
-// {
-//     fn write(&mut self) -> Option<Data<M2RigidBodyMotions>> {
-//         Some(self.estimates.0.clone().into())
-//     }
-// }
-// impl<A> Write<M1Modes> for MergeReconstructor<A, M1Modes>
-// where
-//     A: UniqueIdentifier,
-// {
-//     fn write(&mut self) -> Option<Data<M1Modes>> {
-//         Some(self.estimates.1.clone().into())
-//     }
-// }
+
+/// Implements [Write] of the estimates of the first 2 blocks for their physical quantity
+///
+/// A type can be written as the first block whatever the second block is,
+/// but the second block must be implemented for each pair of types
+/// to avoid overlapping implementations when both blocks are of the same type
+macro_rules! impl_write_blocks {
+    (first: $($a:ty),* ; second: $(($a2:ty, $b2:ty)),*) => {
+        $(
+            impl<B> Write<$a> for MergeReconstructor<CalibrationMode, $a, B> {
+                fn write(&mut self) -> Option<Data<$a>> {
+                    self.estimates.first().map(|estimate| estimate.clone().into())
+                }
+            }
+        )*
+        $(
+            impl Write<$b2> for MergeReconstructor<CalibrationMode, $a2, $b2> {
+                fn write(&mut self) -> Option<Data<$b2>> {
+                    self.estimates.get(1).map(|estimate| estimate.clone().into())
+                }
+            }
+        )*
+    };
+}
+impl_write_blocks! {
+    first: M2RigidBodyMotions, M1RigidBodyMotions, M1Modes;
+    second:
+        (M2RigidBodyMotions, M1Modes),
+        (M2RigidBodyMotions, M1RigidBodyMotions),
+        (M1RigidBodyMotions, M1Modes),
+        (M1RigidBodyMotions, M2RigidBodyMotions),
+        (M1Modes, M2RigidBodyMotions),
+        (M1Modes, M1RigidBodyMotions)
+}
 
 #[cfg(test)]
 mod tests {
     pub use super::*;
+    use crate::fixtures::{N_SEGMENT, calibrations, pickle};
 
     #[test]
     fn merge() -> Result<(), Box<dyn Error>> {
@@ -289,6 +752,159 @@ mod tests {
             None,
         )?;
         println!("{sh48_merge_recon}");
+        assert_eq!(sh48_merge_recon.estimate_sizes(), &[42, 27 * 7]);
+        Ok(())
+    }
+
+    const N_SLOPE: usize = 20;
+    const N_MODE: usize = 3;
+
+    fn synthetic(name: &str, calibs: Vec<Calib<CalibrationMode>>) -> PathBuf {
+        pickle(&format!("gmt-ns-im_merge_{name}.pkl"), calibs).unwrap()
+    }
+    fn builder<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = P>,
+    ) -> MergeReconstructorBuilder<(), ()> {
+        MergeReconstructorBuilder::default().calibrations(paths)
+    }
+
+    #[test]
+    fn segment_count() {
+        let a = synthetic("segment_count_a", calibrations(N_SLOPE, N_MODE, 1));
+        let mut calibs = calibrations(N_SLOPE, N_MODE, 10);
+        calibs.pop();
+        let b = synthetic("segment_count_b", calibs);
+        assert!(matches!(
+            builder([a, b]).build(),
+            Err(MergeError::SegmentCount {
+                a_segments: N_SEGMENT,
+                b_segments: 6,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn row_count() {
+        let a = synthetic("row_count_a", calibrations(N_SLOPE, N_MODE, 1));
+        let b = synthetic("row_count_b", calibrations(N_SLOPE / 2, N_MODE, 10));
+        assert!(matches!(
+            builder([a, b]).build(),
+            Err(MergeError::RowCount {
+                segment: 1,
+                a_rows: N_SLOPE,
+                b_rows: 10,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn mask() {
+        let a = synthetic("mask_a", calibrations(N_SLOPE, N_MODE, 1));
+        // the calibrations of the segments are swapped with their masks
+        let mut calibs = calibrations(N_SLOPE, N_MODE, 10);
+        calibs.reverse();
+        let b = synthetic("mask_b", calibs);
+        assert!(matches!(
+            builder([a, b]).build(),
+            Err(MergeError::Mask { segment: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rank_deficiency() {
+        let a = synthetic("rank_deficiency", calibrations(N_SLOPE, N_MODE, 1));
+        match builder([&a, &a]).build() {
+            Err(MergeError::RankDeficiency {
+                segment,
+                paths,
+                rank,
+                n_cols,
+            }) => {
+                assert_eq!((segment, rank, n_cols), (1, N_MODE, 2 * N_MODE));
+                assert_eq!(paths, vec![a.clone(), a.clone()]);
+            }
+            other => panic!("expected a rank deficiency, found {:?}", other.err()),
+        }
+        // the discarded singular values are not a rank deficiency
+        assert!(
+            builder([&a, &a])
+                .svd_truncation(vec![N_MODE; N_SEGMENT])
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn noise_covariance() {
+        let a = synthetic("noise_covariance", calibrations(N_SLOPE, N_MODE, 1));
+        let noise = NoiseCovariance::Diagonal(vec![vec![1.; N_SLOPE - 1]; N_SEGMENT]);
+        assert!(matches!(
+            builder([a]).noise_covariance(noise).build(),
+            Err(MergeError::NoiseCovariance {
+                segment: 1,
+                expected: N_SLOPE,
+                found: 19,
+            })
+        ));
+    }
+
+    #[test]
+    fn mode_mask() -> Result<(), Box<dyn Error>> {
+        let a = synthetic("mode_mask_a", calibrations(N_SLOPE, N_MODE, 1));
+        let b = synthetic("mode_mask_b", calibrations(N_SLOPE, N_MODE, 10));
+        let mut recon = builder([a, b]).build()?;
+        let n = 2 * N_SEGMENT * N_MODE;
+        assert!(matches!(
+            recon.set_mode_mask(vec![true; n - 1]),
+            Err(MergeError::ModeMask { expected, found }) if expected == n && found == n - 1
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn reconstructor_matrix() -> Result<(), Box<dyn Error>> {
+        let a = synthetic("reconstructor_matrix_a", calibrations(N_SLOPE, N_MODE, 1));
+        let b = synthetic("reconstructor_matrix_b", calibrations(N_SLOPE, N_MODE, 10));
+        let mut recon = builder([a, b]).build()?;
+        let mut matrix = vec![vec![0f64; 2 * N_MODE * N_SLOPE]; N_SEGMENT];
+        matrix[3].pop();
+        assert!(matches!(
+            recon.set_reconstructor_matrix(&matrix),
+            Err(MergeError::ReconstructorMatrix {
+                segment: 4,
+                expected,
+                found,
+            }) if expected == 2 * N_MODE * N_SLOPE && found == expected - 1
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn estimate_sizes() -> Result<(), Box<dyn Error>> {
+        let a = synthetic("estimate_sizes_a", calibrations(N_SLOPE, N_MODE, 1));
+        let b = synthetic("estimate_sizes_b", calibrations(N_SLOPE, N_MODE, 10));
+        let mut recon = builder([&a, &b]).build()?;
+        let other = builder([&a]).build()?;
+        let path = std::env::temp_dir().join("gmt-ns-im_merge_estimate_sizes.bin");
+        other.save(&path)?;
+        assert!(matches!(
+            recon.swap(&path),
+            Err(MergeError::EstimateSizes { expected, found, .. })
+                if expected == [N_SEGMENT * N_MODE; 2] && found == [N_SEGMENT * N_MODE]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn format() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join("gmt-ns-im_merge_format.bin");
+        std::fs::write(&path, "not a merge reconstructor")?;
+        assert!(matches!(
+            MergeReconstructor::<CalibrationMode, (), ()>::load(&path),
+            Err(MergeError::Format(_))
+        ));
         Ok(())
     }
 }
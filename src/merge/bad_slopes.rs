@@ -0,0 +1,237 @@
+use gmt_dos_clients_crseo::calibration::CalibrationMode;
+use interface::{Data, UID, Write};
+
+use super::MergeReconstructor;
+
+/// Number of slopes rejected by a [MergeReconstructor] for each segment
+#[derive(UID)]
+pub enum RejectedSlopes {}
+
+/// Handling of the invalid slopes of a [MergeReconstructor]
+///
+/// Slopes are invalid if they are NaN or infinite, if they exceed the slope threshold,
+/// or if they are missing from a sensor data frame shorter than expected (dropped lenslets).
+/// The estimate of a segment without any valid slope is zero
+/// and the estimates are not updated until the first non-empty sensor data frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BadSlopes {
+    /// the invalid slopes are set to zero
+    #[default]
+    Zeroed,
+    /// the invalid slopes are given a zero weight in the weighted least-squares fit of the segment
+    ///
+    /// The calibration of the segment is inverted again with the inverse of the slope noise covariance
+    /// of the valid slopes as weights and with the Tikhonov regularization if any,
+    /// the SVD truncation is ignored.
+    /// The pseudo-inverse is reused as long as the same slopes are invalid.
+    /// Without a slope noise covariance, the estimate is the same as with [BadSlopes::Reduced]
+    Reweighted,
+    /// the calibration of the segment is inverted again without the rows of the invalid slopes
+    ///
+    /// The pseudo-inverse is reused as long as the same slopes are invalid,
+    /// the reduced calibrations are inverted with the Tikhonov regularization if any,
+    /// the SVD truncation and the slope noise covariance are ignored
+    Reduced,
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Sets how the invalid slopes are handled
+    pub fn bad_slopes(&mut self, bad_slopes: BadSlopes) -> &mut Self {
+        self.bad_slopes = bad_slopes;
+        self.kernels.clear();
+        self
+    }
+    /// Rejects the slopes which absolute value is larger than `threshold`
+    pub fn slope_threshold(&mut self, threshold: Option<f64>) -> &mut Self {
+        self.slope_threshold = threshold;
+        self
+    }
+    /// Number of slopes rejected for each segment during the last update
+    pub fn rejected_slopes(&self) -> &[usize] {
+        &self.rejected_slopes
+    }
+}
+
+impl<A, B> Write<RejectedSlopes> for MergeReconstructor<CalibrationMode, A, B> {
+    fn write(&mut self) -> Option<Data<RejectedSlopes>> {
+        Some(
+            self.rejected_slopes
+                .iter()
+                .map(|&n| n as f64)
+                .collect::<Vec<f64>>()
+                .into(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use gmt_dos_clients_io::optics::SensorData;
+    use interface::{Read, Update};
+
+    use super::*;
+    use crate::{
+        MergeReconstructorBuilder, MergedEstimate, NoiseCovariance,
+        fixtures::{N_SEGMENT, calibrations, pickle, random, sensor},
+    };
+
+    const N_SLOPE: usize = 20;
+    const N_MODE: usize = 3;
+
+    type Merge = MergeReconstructor<CalibrationMode>;
+
+    fn synthetic(name: &str) -> Result<Merge, Box<dyn Error>> {
+        let path = pickle(
+            &format!("gmt-ns-im_bad_slopes_{name}.pkl"),
+            calibrations(N_SLOPE, N_MODE, 1),
+        )?;
+        Ok(MergeReconstructorBuilder::default()
+            .calibration(path)
+            .build()?)
+    }
+
+    fn reconstruct(recon: &mut Merge, slopes: &[f64]) -> Vec<f64> {
+        <_ as Read<SensorData>>::read(recon, Data::new(slopes.to_vec()));
+        recon.update();
+        <_ as Write<MergedEstimate>>::write(recon).unwrap().to_vec()
+    }
+
+    fn assert_close(estimate: &[f64], expected: &[f64]) {
+        for (k, (e, x)) in estimate.iter().zip(expected).enumerate() {
+            assert!((e - x).abs() < 1e-9, "mode #{k}: {e} instead of {x}");
+        }
+    }
+
+    #[test]
+    fn non_finite() -> Result<(), Box<dyn Error>> {
+        let mut recon = synthetic("non_finite")?;
+        let mut slopes = random(N_SEGMENT * N_SLOPE, 42);
+        slopes[3] = 0.;
+        slopes[N_SLOPE + 5] = 0.;
+        let expected = reconstruct(&mut recon, &slopes);
+        slopes[3] = f64::NAN;
+        slopes[N_SLOPE + 5] = f64::INFINITY;
+        assert_close(&reconstruct(&mut recon, &slopes), &expected);
+        assert_eq!(recon.rejected_slopes(), &[1, 1, 0, 0, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn short_data() -> Result<(), Box<dyn Error>> {
+        let mut recon = synthetic("short_data")?;
+        let mut slopes = random(N_SEGMENT * N_SLOPE, 42);
+        let n = slopes.len();
+        slopes[n - 5..].iter_mut().for_each(|x| *x = 0.);
+        let expected = reconstruct(&mut recon, &slopes);
+        assert_close(&reconstruct(&mut recon, &slopes[..n - 5]), &expected);
+        assert_eq!(recon.rejected_slopes(), &[0, 0, 0, 0, 0, 0, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_data() -> Result<(), Box<dyn Error>> {
+        for bad_slopes in [BadSlopes::Zeroed, BadSlopes::Reweighted, BadSlopes::Reduced] {
+            let mut recon = synthetic("empty_data")?;
+            recon.bad_slopes(bad_slopes);
+            // no sensor data before the first frame
+            recon.update();
+            let estimate = <_ as Write<MergedEstimate>>::write(&mut recon).unwrap();
+            assert!(estimate.iter().all(|x| *x == 0.), "{bad_slopes:?}");
+            // a segment without any valid slope
+            let mut slopes = random(N_SEGMENT * N_SLOPE, 42);
+            slopes[..N_SLOPE].iter_mut().for_each(|x| *x = f64::NAN);
+            let estimate = reconstruct(&mut recon, &slopes);
+            assert!(estimate.iter().all(|x| x.is_finite()), "{bad_slopes:?}");
+            assert!(
+                estimate[..N_MODE].iter().all(|x| *x == 0.),
+                "{bad_slopes:?}"
+            );
+            assert_eq!(recon.rejected_slopes()[0], N_SLOPE);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reweighted() -> Result<(), Box<dyn Error>> {
+        let calibs = calibrations(N_SLOPE, N_MODE, 1);
+        let path = pickle("gmt-ns-im_bad_slopes_reweighted.pkl", calibs.clone())?;
+        let variances: Vec<Vec<f64>> = (0..N_SEGMENT)
+            .map(|i| {
+                random(N_SLOPE, 100 + i as u64)
+                    .into_iter()
+                    .map(|x| 1.5 + x)
+                    .collect()
+            })
+            .collect();
+        let covariances: Vec<Vec<f64>> = variances
+            .iter()
+            .map(|v| {
+                (0..N_SLOPE * N_SLOPE)
+                    .map(|k| {
+                        if k % (N_SLOPE + 1) == 0 {
+                            v[k / (N_SLOPE + 1)]
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let build = |noise: Option<NoiseCovariance>, bad_slopes| -> Result<Merge, Box<dyn Error>> {
+            let builder = MergeReconstructorBuilder::default().calibration(&path);
+            let mut recon = match noise {
+                Some(noise) => builder.noise_covariance(noise),
+                None => builder,
+            }
+            .build()?;
+            recon.bad_slopes(bad_slopes);
+            Ok(recon)
+        };
+
+        // 5 invalid slopes in the 1st segment
+        let modes = random(N_SEGMENT * N_MODE, 42);
+        let mut slopes = sensor(&calibs, &modes);
+        slopes[..5].iter_mut().for_each(|x| *x = f64::NAN);
+        // the estimate of noise-free slopes is not degraded by the invalid slopes
+        for noise in [None, Some(NoiseCovariance::Diagonal(variances.clone()))] {
+            let mut recon = build(noise, BadSlopes::Reweighted)?;
+            assert_close(&reconstruct(&mut recon, &slopes), &modes);
+            assert_eq!(recon.rejected_slopes()[0], 5);
+        }
+        // whereas the zeroed slopes bias the estimate of the segment
+        let zeroed = reconstruct(&mut build(None, BadSlopes::Zeroed)?, &slopes);
+        assert!(
+            zeroed[..N_MODE]
+                .iter()
+                .zip(&modes)
+                .any(|(x, m)| (x - m).abs() > 1e-6)
+        );
+
+        // noisy slopes
+        let mut slopes = random(N_SEGMENT * N_SLOPE, 7);
+        slopes[..5].iter_mut().for_each(|x| *x = f64::NAN);
+        assert_close(
+            &reconstruct(&mut build(None, BadSlopes::Reweighted)?, &slopes),
+            &reconstruct(&mut build(None, BadSlopes::Reduced)?, &slopes),
+        );
+        assert_close(
+            &reconstruct(
+                &mut build(
+                    Some(NoiseCovariance::Diagonal(variances)),
+                    BadSlopes::Reweighted,
+                )?,
+                &slopes,
+            ),
+            &reconstruct(
+                &mut build(
+                    Some(NoiseCovariance::Full(covariances)),
+                    BadSlopes::Reweighted,
+                )?,
+                &slopes,
+            ),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fs::File,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use gmt_dos_clients_crseo::calibration::{CalibrationMode, algebra::CalibProps};
+use serde::{Deserialize, Serialize};
+
+use super::{MergeError, MergeReconstructor, singular_values};
+
+/// Cosines of the angles between the columns of 2 blocks of a merged calibration
+///
+/// `matrix[i][j]` is the cosine between the `i`th column of block `a`
+/// and the `j`th column of block `b`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossTalk {
+    pub a: usize,
+    pub b: usize,
+    pub matrix: Vec<Vec<f64>>,
+}
+
+/// Conditioning of the merged calibration of a segment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentDiagnostics {
+    /// segment # in [1,7]
+    pub segment: usize,
+    pub n_rows: usize,
+    /// number of columns of each block
+    pub block_sizes: Vec<usize>,
+    /// normalization factor of each block
+    pub norms: Vec<f64>,
+    /// singular values of the normalized merged calibration in decreasing order
+    pub singular_values: Vec<f64>,
+    /// ratio of the largest to the smallest singular value
+    pub condition_number: f64,
+    /// number of singular values discarded by the SVD truncation
+    pub n_truncated: usize,
+    /// condition number of the truncated merged calibration
+    pub truncated_condition_number: f64,
+    pub cross_talk: Vec<CrossTalk>,
+}
+
+/// [MergeReconstructor] diagnostics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeDiagnostics {
+    pub calibrations: Vec<PathBuf>,
+    pub svd_truncation: Option<Vec<usize>>,
+    pub segments: Vec<SegmentDiagnostics>,
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Computes the conditioning of the merged calibration of each segment
+    pub fn diagnostics(&mut self) -> MergeDiagnostics {
+        let svd_truncation = self.svd_truncation.clone();
+        let segments = self
+            .recon
+            .calib_pinv()
+            .zip(&self.blocks)
+            .zip(&self.norms)
+            .enumerate()
+            .map(|(i, (((c, _), blocks), norms))| {
+                let n_rows = c.n_rows();
+                let block_sizes: Vec<usize> = blocks.iter().map(|(_, n)| *n).collect();
+                let mat = c.as_slice();
+                let singular_values = singular_values(mat, n_rows, c.n_cols());
+                let n_truncated = svd_truncation
+                    .as_ref()
+                    .and_then(|n| n.get(i).cloned())
+                    .unwrap_or_default();
+                let condition = |n: usize| {
+                    singular_values
+                        .first()
+                        .zip(singular_values.get(n.saturating_sub(1)))
+                        .map_or(f64::INFINITY, |(s_max, s_min)| s_max / s_min)
+                };
+                let offsets: Vec<usize> = block_sizes
+                    .iter()
+                    .scan(0, |o, &n| {
+                        *o += n;
+                        Some(*o - n)
+                    })
+                    .collect();
+                let column = |k: usize| &mat[k * n_rows..(k + 1) * n_rows];
+                let mut cross_talk = vec![];
+                for a in 0..block_sizes.len() {
+                    for b in a + 1..block_sizes.len() {
+                        let matrix = (0..block_sizes[a])
+                            .map(|i| {
+                                let x = column(offsets[a] + i);
+                                (0..block_sizes[b])
+                                    .map(|j| cosine(x, column(offsets[b] + j)))
+                                    .collect()
+                            })
+                            .collect();
+                        cross_talk.push(CrossTalk { a, b, matrix });
+                    }
+                }
+                SegmentDiagnostics {
+                    segment: i + 1,
+                    n_rows,
+                    condition_number: condition(singular_values.len()),
+                    truncated_condition_number: condition(
+                        singular_values.len().saturating_sub(n_truncated),
+                    ),
+                    block_sizes,
+                    norms: norms.clone(),
+                    singular_values,
+                    n_truncated,
+                    cross_talk,
+                }
+            })
+            .collect();
+        MergeDiagnostics {
+            calibrations: self.calibrations.clone(),
+            svd_truncation,
+            segments,
+        }
+    }
+}
+
+fn cosine(x: &[f64], y: &[f64]) -> f64 {
+    let dot = |x: &[f64], y: &[f64]| x.iter().zip(y).map(|(x, y)| x * y).sum::<f64>();
+    dot(x, y) / (dot(x, x) * dot(y, y)).sqrt()
+}
+
+impl MergeDiagnostics {
+    /// Writes the diagnostics to a JSON file
+    pub fn to_json(&self, path: impl AsRef<Path>) -> Result<(), MergeError> {
+        serde_json::to_writer_pretty(File::create(path.as_ref())?, self)?;
+        Ok(())
+    }
+    /// Writes the diagnostics to CSV files in a given directory
+    ///
+    /// The files are:
+    ///  * `segments.csv`: size, normalization factors and condition numbers of each segment
+    ///  * `singular_values.csv`: singular values of each segment
+    ///  * `cross_talk.csv`: cross-talk between the columns of each pair of blocks of each segment
+    pub fn to_csv(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let n_block = self.calibrations.len();
+
+        let mut file = File::create(dir.join("segments.csv"))?;
+        write!(
+            file,
+            "segment,n_rows,n_cols,condition_number,n_truncated,truncated_condition_number"
+        )?;
+        for k in 0..n_block {
+            write!(file, ",n_cols_{k},norm_{k}")?;
+        }
+        writeln!(file)?;
+        for s in &self.segments {
+            write!(
+                file,
+                "{},{},{},{:e},{},{:e}",
+                s.segment,
+                s.n_rows,
+                s.block_sizes.iter().sum::<usize>(),
+                s.condition_number,
+                s.n_truncated,
+                s.truncated_condition_number
+            )?;
+            for (n, norm) in s.block_sizes.iter().zip(&s.norms) {
+                write!(file, ",{n},{norm:e}")?;
+            }
+            writeln!(file)?;
+        }
+
+        let mut file = File::create(dir.join("singular_values.csv"))?;
+        writeln!(file, "segment,index,singular_value")?;
+        for s in &self.segments {
+            for (i, v) in s.singular_values.iter().enumerate() {
+                writeln!(file, "{},{i},{v:e}", s.segment)?;
+            }
+        }
+
+        let mut file = File::create(dir.join("cross_talk.csv"))?;
+        writeln!(file, "segment,block_a,block_b,column_a,column_b,cosine")?;
+        for s in &self.segments {
+            for CrossTalk { a, b, matrix } in &s.cross_talk {
+                for (i, row) in matrix.iter().enumerate() {
+                    for (j, v) in row.iter().enumerate() {
+                        writeln!(file, "{},{a},{b},{i},{j},{v:e}", s.segment)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gmt_dos_clients_crseo::calibration::Calib;
+
+    use super::*;
+    use crate::{
+        MergeReconstructorBuilder,
+        fixtures::{N_SEGMENT, pickle},
+    };
+
+    const N_SLOPE: usize = 4;
+
+    /// Pickles one-mode calibrations, the calibration of each segment is `column`
+    fn synthetic(name: &str, column: [f64; N_SLOPE]) -> PathBuf {
+        let calibs = (0..N_SEGMENT)
+            .map(|i| {
+                let mask: Vec<bool> = (0..N_SEGMENT * N_SLOPE).map(|k| k / N_SLOPE == i).collect();
+                Calib::builder()
+                    .c(column.to_vec())
+                    .n_cols(1)
+                    .mask(mask)
+                    .mode(CalibrationMode::modes(1, 1e-6))
+                    .build()
+            })
+            .collect();
+        pickle(&format!("gmt-ns-im_diagnostics_{name}.pkl"), calibs).unwrap()
+    }
+
+    #[test]
+    fn conditioning() -> Result<(), MergeError> {
+        let a = synthetic("conditioning_a", [1., 0., 0., 0.]);
+        let b = synthetic("conditioning_b", [0.6, 0.8, 0., 0.]);
+        let mut recon = MergeReconstructorBuilder::<(), ()>::default()
+            .calibrations([a, b])
+            .build()?;
+        let diagnostics = recon.diagnostics();
+        assert_eq!(diagnostics.segments.len(), N_SEGMENT);
+        for s in &diagnostics.segments {
+            assert_eq!((s.n_rows, s.block_sizes.clone()), (N_SLOPE, vec![1, 1]));
+            // singular values of [a/norm_a, b/norm_b], a and b being unit vectors with a cosine of 0.6
+            let (x, y) = (s.norms[0].powi(-2), s.norms[1].powi(-2));
+            let d = ((0.5 * (x - y)).powi(2) + x * y * 0.36).sqrt();
+            let expected = [(0.5 * (x + y) + d).sqrt(), (0.5 * (x + y) - d).sqrt()];
+            for (s, e) in s.singular_values.iter().zip(expected) {
+                assert!(
+                    (s - e).abs() < 1e-12 * e,
+                    "singular value {s}, expected {e}"
+                );
+            }
+            assert_eq!(s.singular_values.len(), 2);
+            assert!((s.condition_number - expected[0] / expected[1]).abs() < 1e-9);
+            assert!((s.cross_talk[0].matrix[0][0] - 0.6).abs() < 1e-12);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rank_deficient() -> Result<(), MergeError> {
+        let a = synthetic("rank_deficient_a", [1., 0., 0., 0.]);
+        let b = synthetic("rank_deficient_b", [2., 0., 0., 0.]);
+        let builder = MergeReconstructorBuilder::<(), ()>::default().calibrations([a, b]);
+        assert!(matches!(
+            builder.clone().build(),
+            Err(MergeError::RankDeficiency { .. })
+        ));
+        let diagnostics = builder.build_unchecked()?.diagnostics();
+        for s in &diagnostics.segments {
+            assert!(s.condition_number > 1e12, "{}", s.condition_number);
+            assert!((s.cross_talk[0].matrix[0][0] - 1.).abs() < 1e-12);
+        }
+        Ok(())
+    }
+}
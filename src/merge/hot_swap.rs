@@ -0,0 +1,190 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use faer::MatRef;
+use gmt_dos_clients_crseo::calibration::{CalibrationMode, algebra::CalibProps};
+use interface::{Data, Read, UID};
+
+use super::{MergeError, MergeReconstructor};
+
+/// Replacement pseudo-inverses of a [MergeReconstructor] or of a [SwapReconstructor](crate::SwapReconstructor)
+///
+/// One column-major matrix per segment, of the size of the transpose of the segment calibration
+#[derive(UID)]
+#[uid(data = Vec<Vec<f64>>)]
+pub enum ReconstructorMatrix {}
+
+/// Saved reconstructor watched for modifications
+#[derive(Debug, Clone)]
+pub(crate) struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Watch {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self {
+            modified: modified(&path),
+            path,
+        }
+    }
+    /// Returns the path of the file if it has been modified since the last call
+    ///
+    /// A file that fails to load is tried again only once it is modified again,
+    /// e.g. after it has been completely written
+    pub(crate) fn poll(&mut self) -> Option<PathBuf> {
+        let last_modified = modified(&self.path);
+        if last_modified.is_none() || last_modified == self.modified {
+            return None;
+        }
+        self.modified = last_modified;
+        Some(self.path.clone())
+    }
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Replaces the reconstructor with the one saved in `path` (see [MergeReconstructor::save])
+    /// whenever the file is modified
+    ///
+    /// The file is checked at the beginning of each update,
+    /// a reconstructor that cannot be loaded or with different estimate sizes is ignored
+    /// until the file is modified again
+    pub fn watch(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.watch = Some(Watch::new(path));
+        self
+    }
+    /// Replaces the pseudo-inverse of each segment
+    ///
+    /// The matrices are used as is for the masked modes [Zeroed](super::MaskedModes::Zeroed)
+    /// and the invalid slopes [Zeroed](super::BadSlopes::Zeroed),
+    /// the other policies still invert the calibrations again
+    pub fn set_reconstructor_matrix(
+        &mut self,
+        matrix: &[Vec<f64>],
+    ) -> Result<&mut Self, MergeError> {
+        let shapes: Vec<(usize, usize)> = self
+            .recon
+            .calib_pinv()
+            .map(|(c, _)| (c.n_cols(), c.n_rows()))
+            .collect();
+        for (i, (n_rows, n_cols)) in shapes.iter().enumerate() {
+            let found = matrix.get(i).map_or(0, |m| m.len());
+            if found != n_rows * n_cols {
+                return Err(MergeError::ReconstructorMatrix {
+                    segment: i + 1,
+                    expected: n_rows * n_cols,
+                    found,
+                });
+            }
+        }
+        self.recon.pinv().zip(matrix.iter().zip(&shapes)).for_each(
+            |(p, (m, &(n_rows, n_cols)))| {
+                p.transform(|_| MatRef::from_column_major_slice(m, n_rows, n_cols).to_owned())
+            },
+        );
+        Ok(self.masked_modes(self.masked_modes))
+    }
+    /// Replaces the reconstructor with another one with the same estimate sizes
+    pub(super) fn swap(&mut self, path: &Path) -> Result<(), MergeError> {
+        let other = Self::load(path)?;
+        if other.estimate_sizes != self.estimate_sizes {
+            return Err(MergeError::EstimateSizes {
+                path: path.to_path_buf(),
+                expected: self.estimate_sizes.clone(),
+                found: other.estimate_sizes,
+            });
+        }
+        self.recon = other.recon;
+        self.blocks = other.blocks;
+        self.calibrations = other.calibrations;
+        self.sha256 = other.sha256;
+        self.svd_truncation = other.svd_truncation;
+        self.regularization = other.regularization;
+        self.noise = other.noise;
+        self.norms = other.norms;
+        self.masked_modes(self.masked_modes);
+        Ok(())
+    }
+    /// Applies the pending replacements, called at the beginning of each update
+    pub(super) fn hot_swap(&mut self) {
+        if let Some(matrix) = self.pending_matrix.take() {
+            match self.set_reconstructor_matrix(&matrix) {
+                Ok(_) => log::info!("merge reconstructor matrix replaced"),
+                Err(e) => log::warn!("merge reconstructor matrix ignored: {e}"),
+            }
+        }
+        let Some(path) = self.watch.as_mut().and_then(Watch::poll) else {
+            return;
+        };
+        match self.swap(&path) {
+            Ok(_) => log::info!("merge reconstructor replaced with {path:?}"),
+            Err(e) => log::warn!("merge reconstructor not replaced with {path:?}: {e}"),
+        }
+    }
+}
+
+impl<A, B> Read<ReconstructorMatrix> for MergeReconstructor<CalibrationMode, A, B> {
+    fn read(&mut self, data: Data<ReconstructorMatrix>) {
+        self.pending_matrix = Some(data.into_arc());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        error::Error,
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::{
+        MergeReconstructorBuilder,
+        fixtures::{calibrations, pickle},
+    };
+
+    type Merge = MergeReconstructor<CalibrationMode>;
+
+    #[test]
+    fn corrupt_file() -> Result<(), Box<dyn Error>> {
+        let a = pickle("gmt-ns-im_hot_swap_a.pkl", calibrations(20, 3, 1))?;
+        let b = pickle("gmt-ns-im_hot_swap_b.pkl", calibrations(20, 3, 10))?;
+        let mut recon: Merge = MergeReconstructorBuilder::default()
+            .calibration(&a)
+            .build()?;
+        let other: Merge = MergeReconstructorBuilder::default()
+            .calibration(&b)
+            .build()?;
+
+        let path = std::env::temp_dir().join("gmt-ns-im_hot_swap.bin");
+        let _ = fs::remove_file(&path);
+        recon.watch(&path);
+        let t0 = SystemTime::now();
+        fs::write(&path, "half-written")?;
+        File::options().write(true).open(&path)?.set_modified(t0)?;
+        recon.hot_swap();
+        assert_eq!(recon.watch.as_ref().unwrap().modified, Some(t0));
+
+        // the file is not read again as long as it is not modified
+        other.save(&path)?;
+        File::options().write(true).open(&path)?.set_modified(t0)?;
+        recon.hot_swap();
+        assert_eq!(recon.calibrations, [a]);
+
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(t0 + Duration::from_secs(1))?;
+        recon.hot_swap();
+        assert_eq!(recon.calibrations, [b]);
+        Ok(())
+    }
+}
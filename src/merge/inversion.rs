@@ -0,0 +1,342 @@
+use faer::{Mat, MatRef};
+use gmt_dos_clients_crseo::calibration::CalibrationMode;
+use serde::{Deserialize, Serialize};
+
+use super::MergeError;
+
+/// Tikhonov regularization of the merged calibrations
+///
+/// The penalty on the `m`th mode of block `k` is `block_weights[k] * mode_weights[k][m]`,
+/// missing block weights default to 0 and missing mode weights to 1.
+/// The weights apply to the normalized calibrations, i.e. relative to unit-norm blocks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Regularization {
+    pub block_weights: Vec<f64>,
+    pub mode_weights: Vec<Vec<f64>>,
+}
+
+impl Regularization {
+    /// Regularization with a weight per block
+    pub fn new(block_weights: Vec<f64>) -> Self {
+        Self {
+            block_weights,
+            ..Default::default()
+        }
+    }
+    /// Sets the weights of the modes of a block
+    pub fn mode_weights(mut self, block: usize, weights: Vec<f64>) -> Self {
+        if self.mode_weights.len() <= block {
+            self.mode_weights.resize(block + 1, vec![]);
+        }
+        self.mode_weights[block] = weights;
+        self
+    }
+    pub(super) fn weight(&self, block: usize, mode: usize) -> f64 {
+        self.block_weights.get(block).cloned().unwrap_or_default()
+            * self
+                .mode_weights
+                .get(block)
+                .and_then(|w| w.get(mode))
+                .cloned()
+                .unwrap_or(1.)
+    }
+}
+
+/// Slope noise covariance of the minimum-variance inversion
+///
+/// The per-segment variances and covariance matrices are given for the valid slopes only.
+/// The noise covariance weights the least-squares fit of the slopes,
+/// the prior on the modes is given with the [Regularization]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NoiseCovariance {
+    /// same variance for all the slopes
+    Scalar(f64),
+    /// variance of each slope of each segment
+    Diagonal(Vec<Vec<f64>>),
+    /// covariance matrix (column-major) of the slopes of each segment
+    Full(Vec<Vec<f64>>),
+}
+
+impl NoiseCovariance {
+    /// Noise covariance of the `i`th segment only, as the covariance of segment 0
+    pub(super) fn segment(&self, i: usize) -> Self {
+        match self {
+            Self::Scalar(variance) => Self::Scalar(*variance),
+            Self::Diagonal(variances) => {
+                Self::Diagonal(variances.get(i).cloned().into_iter().collect())
+            }
+            Self::Full(covariances) => {
+                Self::Full(covariances.get(i).cloned().into_iter().collect())
+            }
+        }
+    }
+}
+
+/// Regularized inverse of the calibration `c` of a segment
+///
+/// Computes `(C^T N^-1 C + G^2)^-1 C^T N^-1`, `N` being the noise covariance
+/// and `G` the diagonal matrix of the regularization weights
+pub(super) fn regularized(
+    segment: usize,
+    c: MatRef<'_, f64>,
+    block_sizes: &[usize],
+    regularization: Option<&Regularization>,
+    noise: Option<&NoiseCovariance>,
+) -> Result<Mat<f64>, MergeError> {
+    let gamma: Vec<f64> = regularization.map_or_else(
+        || vec![0.; c.ncols()],
+        |r| {
+            block_sizes
+                .iter()
+                .enumerate()
+                .flat_map(|(k, &n)| (0..n).map(move |m| r.weight(k, m)))
+                .collect()
+        },
+    );
+    weighted(segment, c, &gamma, noise, &vec![false; c.nrows()])
+}
+
+/// Regularized inverse of the calibration `c` of a segment with zero weights for the invalid slopes
+///
+/// Computes `(C^T W C + G^2)^-1 C^T W`, `W` being the inverse of the noise covariance
+/// of the valid slopes padded with zeros for the invalid slopes
+/// and `G` the diagonal matrix of the regularization weights `gamma`
+pub(super) fn weighted(
+    segment: usize,
+    c: MatRef<'_, f64>,
+    gamma: &[f64],
+    noise: Option<&NoiseCovariance>,
+    invalid: &[bool],
+) -> Result<Mat<f64>, MergeError> {
+    let n_rows = c.nrows();
+    let n_cols = c.ncols();
+    let valid = |j: usize, x: f64| if invalid[j] { 0. } else { x };
+
+    // C^T W
+    let ct_w: Mat<f64> = match noise {
+        None => Mat::from_fn(n_cols, n_rows, |i, j| valid(j, c[(j, i)])),
+        Some(NoiseCovariance::Scalar(variance)) => {
+            Mat::from_fn(n_cols, n_rows, |i, j| valid(j, c[(j, i)] / variance))
+        }
+        Some(NoiseCovariance::Diagonal(variances)) => {
+            let variances = noise_covariance(segment, variances, n_rows)?;
+            Mat::from_fn(n_cols, n_rows, |i, j| valid(j, c[(j, i)] / variances[j]))
+        }
+        Some(NoiseCovariance::Full(covariances)) => {
+            let covariance = noise_covariance(segment, covariances, n_rows * n_rows)?;
+            let covariance = MatRef::from_column_major_slice(covariance, n_rows, n_rows);
+            let rows: Vec<usize> = (0..n_rows).filter(|&j| !invalid[j]).collect();
+            let inverse = pinv(
+                Mat::from_fn(rows.len(), rows.len(), |i, j| {
+                    covariance[(rows[i], rows[j])]
+                })
+                .as_ref(),
+            );
+            let mut weights = Mat::<f64>::zeros(n_rows, n_rows);
+            for (i, &ri) in rows.iter().enumerate() {
+                for (j, &rj) in rows.iter().enumerate() {
+                    weights[(ri, rj)] = inverse[(i, j)];
+                }
+            }
+            c.transpose() * weights
+        }
+    };
+
+    let mut m = &ct_w * c;
+    for (i, g) in gamma.iter().enumerate() {
+        m[(i, i)] += g * g;
+    }
+    Ok(pinv(m.as_ref()) * ct_w)
+}
+
+fn noise_covariance(
+    segment: usize,
+    covariances: &[Vec<f64>],
+    expected: usize,
+) -> Result<&[f64], MergeError> {
+    match covariances.get(segment) {
+        Some(covariance) if covariance.len() == expected => Ok(covariance.as_slice()),
+        covariance => Err(MergeError::NoiseCovariance {
+            segment: segment + 1,
+            expected,
+            found: covariance.map_or(0, |c| c.len()),
+        }),
+    }
+}
+
+/// Regularization weights and normalization scale factors of the columns of the calibration of a segment
+pub(super) fn column_weights(
+    blocks: &[(CalibrationMode, usize)],
+    norms: &[f64],
+    regularization: Option<&Regularization>,
+) -> (Vec<f64>, Vec<f64>) {
+    blocks
+        .iter()
+        .zip(norms)
+        .enumerate()
+        .flat_map(|(k, ((_, n), nrm))| {
+            (0..*n).map(move |m| (regularization.map_or(0., |r| r.weight(k, m)), 1. / nrm))
+        })
+        .unzip()
+}
+
+/// Regularized pseudo-inverse of the normalized calibration `c` of a segment
+/// without the masked rows and columns
+///
+/// The pseudo-inverse has the size of `c` transposed with zeros for the masked rows and columns
+/// and the normalization of the columns is undone with the `scale` factors
+pub(super) fn reduced(
+    c: MatRef<'_, f64>,
+    gamma: &[f64],
+    scale: &[f64],
+    masked_rows: &[bool],
+    masked_cols: &[bool],
+) -> Mat<f64> {
+    let rows: Vec<usize> = (0..c.nrows()).filter(|&i| !masked_rows[i]).collect();
+    let cols: Vec<usize> = (0..c.ncols()).filter(|&j| !masked_cols[j]).collect();
+    let reduced = Mat::from_fn(rows.len(), cols.len(), |i, j| c[(rows[i], cols[j])]);
+    let mut m = reduced.transpose() * reduced.as_ref();
+    for (j, &col) in cols.iter().enumerate() {
+        m[(j, j)] += gamma[col] * gamma[col];
+    }
+    let r = pinv(m.as_ref()) * reduced.transpose();
+    let mut pinv = Mat::<f64>::zeros(c.ncols(), c.nrows());
+    for (j, &col) in cols.iter().enumerate() {
+        for (i, &row) in rows.iter().enumerate() {
+            pinv[(col, row)] = r[(j, i)] * scale[col];
+        }
+    }
+    pinv
+}
+
+/// Pseudo-inverse of a matrix
+pub(super) fn pinv(m: MatRef<'_, f64>) -> Mat<f64> {
+    let Ok(svd) = m.thin_svd() else {
+        return Mat::zeros(m.ncols(), m.nrows());
+    };
+    let s: Vec<f64> = svd.S().column_vector().iter().cloned().collect();
+    let s_max = s.iter().cloned().fold(0f64, f64::max);
+    let tol = s_max * m.nrows().max(m.ncols()) as f64 * f64::EPSILON;
+    let n = s.len();
+    let is = Mat::from_fn(
+        n,
+        n,
+        |i, j| {
+            if i == j && s[i] > tol { 1. / s[i] } else { 0. }
+        },
+    );
+    svd.V() * is * svd.U().transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // C = [[1,0],[1,1],[0,1]]
+    fn calibration() -> Mat<f64> {
+        Mat::from_fn(3, 2, |i, j| if i == j || i == j + 1 { 1. } else { 0. })
+    }
+
+    fn assert_close(r: Mat<f64>, expected: [[f64; 3]; 2]) {
+        assert_eq!((r.nrows(), r.ncols()), (2, 3));
+        for (i, row) in expected.iter().enumerate() {
+            for (j, e) in row.iter().enumerate() {
+                assert!(
+                    (r[(i, j)] - e).abs() < 1e-12,
+                    "r[{i},{j}]={}, expected {e}",
+                    r[(i, j)]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn least_squares() -> Result<(), MergeError> {
+        // (C^T C)^-1 C^T
+        let expected = [[2., 1., -1.], [-1., 1., 2.]].map(|row| row.map(|x| x / 3.));
+        let c = calibration();
+        assert_close(regularized(0, c.as_ref(), &[1, 1], None, None)?, expected);
+        // a scalar noise covariance does not change the inverse
+        let noise = NoiseCovariance::Scalar(4.);
+        assert_close(
+            regularized(0, c.as_ref(), &[1, 1], None, Some(&noise))?,
+            expected,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tikhonov() -> Result<(), MergeError> {
+        let c = calibration();
+        // (C^T C + diag(1,0.25))^-1 C^T
+        let regularization = Regularization::new(vec![1., 0.5]);
+        assert_close(
+            regularized(0, c.as_ref(), &[1, 1], Some(&regularization), None)?,
+            [[2.25, 1.25, -1.], [-1., 2., 3.]].map(|row| row.map(|x| x / 5.75)),
+        );
+        // (C^T C + diag(1,4))^-1 C^T
+        let regularization = Regularization::new(vec![2.]).mode_weights(0, vec![0.5, 1.]);
+        assert_close(
+            regularized(0, c.as_ref(), &[2], Some(&regularization), None)?,
+            [[6., 5., -1.], [-1., 2., 3.]].map(|row| row.map(|x| x / 17.)),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn minimum_variance() -> Result<(), MergeError> {
+        let c = calibration();
+        // (C^T N^-1 C)^-1 C^T N^-1 with N = diag(1,2,4)
+        let expected = [[6., 1., -1.], [-4., 4., 3.]].map(|row| row.map(|x| x / 7.));
+        let variances = vec![1., 2., 4.];
+        let noise = NoiseCovariance::Diagonal(vec![variances.clone()]);
+        assert_close(
+            regularized(0, c.as_ref(), &[1, 1], None, Some(&noise))?,
+            expected,
+        );
+        let covariance = (0..9)
+            .map(|k| if k % 4 == 0 { variances[k / 4] } else { 0. })
+            .collect();
+        let noise = NoiseCovariance::Full(vec![covariance]);
+        assert_close(
+            regularized(0, c.as_ref(), &[1, 1], None, Some(&noise))?,
+            expected,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_slopes() -> Result<(), MergeError> {
+        let c = calibration();
+        // the fit of the 1st and 3rd slopes only is exact
+        let expected = [[1., 0., 0.], [0., 0., 1.]];
+        let invalid = [false, true, false];
+        assert_close(
+            weighted(0, c.as_ref(), &[0., 0.], None, &invalid)?,
+            expected,
+        );
+        let covariance = (0..9)
+            .map(|k| if k % 4 == 0 { [1., 2., 4.][k / 4] } else { 0. })
+            .collect();
+        let noise = NoiseCovariance::Full(vec![covariance]);
+        assert_close(
+            weighted(0, c.as_ref(), &[0., 0.], Some(&noise), &invalid)?,
+            expected,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reduced_columns() {
+        let c = calibration();
+        // least-squares fit of the 1st column only, with the scale factors applied
+        let r = reduced(
+            c.as_ref(),
+            &[0., 0.],
+            &[2., 1.],
+            &[false; 3],
+            &[false, true],
+        );
+        assert_close(r, [[1., 1., 0.], [0., 0., 0.]]);
+    }
+}
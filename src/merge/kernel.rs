@@ -0,0 +1,221 @@
+use std::sync::Arc;
+
+use faer::{Accum, Mat, MatMut, MatRef, Par, linalg::matmul::matmul};
+use gmt_dos_clients_crseo::calibration::{CalibrationMode, Modality, algebra::CalibProps};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::{BadSlopes, MaskedModes, MergeReconstructor, NoiseCovariance, inversion};
+
+/// Reconstruction of the estimates of a segment with preallocated buffers
+///
+/// The kernels are derived from the reconstructor at the first update
+/// and after each change of the pseudo-inverses or of the mode mask.
+/// The segment buffers are reused but the estimates of the blocks are copied
+/// at each update if the previous ones are still shared with a reader
+pub(super) struct SegmentKernel {
+    // indices of the valid slopes of the segment in the sensor data
+    rows: Vec<usize>,
+    // pseudo-inverse, without the masked modes if they are removed
+    pinv: Mat<f64>,
+    // normalized calibration, regularization weights and normalization scale factors
+    // for the inversion without the invalid slopes
+    c: Mat<f64>,
+    gamma: Vec<f64>,
+    scale: Vec<f64>,
+    masked_cols: Vec<bool>,
+    // slope noise covariance of the segment
+    noise: Option<NoiseCovariance>,
+    // index of each entry of the estimate of each block in the segment estimate,
+    // `None` for the modes that are not calibrated
+    layout: Vec<Vec<Option<usize>>>,
+    slopes: Vec<f64>,
+    bad: Vec<bool>,
+    n_bad: usize,
+    // pseudo-inverse without the rows or the weights of the invalid slopes
+    bad_pinv: Option<(Vec<bool>, Mat<f64>)>,
+    estimate: Vec<f64>,
+}
+
+impl SegmentKernel {
+    /// Computes the estimate of the segment from the sensor data
+    ///
+    /// Slopes missing from the sensor data are invalid
+    fn reconstruct(&mut self, data: &[f64], bad_slopes: BadSlopes, threshold: Option<f64>) {
+        self.n_bad = 0;
+        for ((x, bad), &k) in self.slopes.iter_mut().zip(&mut self.bad).zip(&self.rows) {
+            let s = data.get(k).copied().unwrap_or(f64::NAN);
+            *bad = !s.is_finite() || threshold.is_some_and(|t| s.abs() > t);
+            if *bad {
+                self.n_bad += 1;
+                *x = 0.;
+            } else {
+                *x = s;
+            }
+        }
+        let n = self.slopes.len();
+        // no valid slope, the estimate is zero
+        if self.n_bad == n {
+            self.estimate.fill(0.);
+            return;
+        }
+        let pinv = if self.n_bad > 0 && bad_slopes != BadSlopes::Zeroed {
+            if self.bad_pinv.as_ref().is_none_or(|(b, _)| *b != self.bad) {
+                let pinv = if bad_slopes == BadSlopes::Reweighted {
+                    self.reweighted()
+                } else {
+                    inversion::reduced(
+                        self.c.as_ref(),
+                        &self.gamma,
+                        &self.scale,
+                        &self.bad,
+                        &self.masked_cols,
+                    )
+                };
+                self.bad_pinv = Some((self.bad.clone(), pinv));
+            }
+            &self.bad_pinv.as_ref().unwrap().1
+        } else {
+            &self.pinv
+        };
+        let n_cols = self.estimate.len();
+        matmul(
+            MatMut::from_column_major_slice_mut(&mut self.estimate, n_cols, 1),
+            Accum::Replace,
+            pinv.as_ref(),
+            MatRef::from_column_major_slice(&self.slopes, n, 1),
+            1.,
+            Par::Seq,
+        );
+    }
+    /// Weighted pseudo-inverse with zero weights for the invalid slopes
+    ///
+    /// The masked modes that are removed are left out of the fit
+    /// and the normalization of the columns is undone with the scale factors
+    fn reweighted(&self) -> Mat<f64> {
+        let c = Mat::from_fn(self.c.nrows(), self.c.ncols(), |i, j| {
+            if self.masked_cols[j] {
+                0.
+            } else {
+                self.c[(i, j)]
+            }
+        });
+        // the noise covariance is checked when the reconstructor is built
+        let Ok(pinv) =
+            inversion::weighted(0, c.as_ref(), &self.gamma, self.noise.as_ref(), &self.bad)
+        else {
+            return self.pinv.clone();
+        };
+        Mat::from_fn(pinv.nrows(), pinv.ncols(), |i, j| {
+            if self.masked_cols[i] {
+                0.
+            } else {
+                pinv[(i, j)] * self.scale[i]
+            }
+        })
+    }
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Derives the reconstruction kernel of each segment
+    fn build_kernels(&mut self) {
+        let masked_columns = match (self.masked_modes, &self.mode_mask) {
+            (MaskedModes::Removed, Some(mask)) => Some(self.masked_columns(mask)),
+            _ => None,
+        };
+        let regularization = self.regularization.as_ref();
+        let noise = self.noise.as_ref();
+        let masked_pinv = self.masked_pinv.as_ref();
+        self.kernels = self
+            .recon
+            .calib_pinv()
+            .zip(&self.blocks)
+            .zip(&self.norms)
+            .enumerate()
+            .map(|(i, (((c, ic), blocks), norms))| {
+                let rows: Vec<usize> = c
+                    .mask_as_slice()
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, &valid)| valid.then_some(k))
+                    .collect();
+                let (gamma, scale) = inversion::column_weights(blocks, norms, regularization);
+                let mut offset = 0;
+                let layout = blocks
+                    .iter()
+                    .map(|(mode, n)| {
+                        // the column # (from 1) of each mode of the estimate, 0 if the mode is not calibrated
+                        let layout = mode
+                            .fill((1..=*n).map(|j| j as f64))
+                            .into_iter()
+                            .map(|j| (j > 0.).then(|| offset + j as usize - 1))
+                            .collect();
+                        offset += n;
+                        layout
+                    })
+                    .collect();
+                SegmentKernel {
+                    pinv: masked_pinv.map_or_else(|| ic.to_owned(), |pinv| pinv[i].clone()),
+                    c: MatRef::from_column_major_slice(c.as_slice(), c.n_rows(), c.n_cols())
+                        .to_owned(),
+                    gamma,
+                    scale,
+                    masked_cols: masked_columns
+                        .as_ref()
+                        .map_or_else(|| vec![false; c.n_cols()], |m| m[i].clone()),
+                    noise: noise.map(|noise| noise.segment(i)),
+                    layout,
+                    slopes: vec![0.; rows.len()],
+                    bad: vec![false; rows.len()],
+                    n_bad: 0,
+                    bad_pinv: None,
+                    estimate: vec![0.; c.n_cols()],
+                    rows,
+                }
+            })
+            .collect();
+    }
+    /// Computes the estimates from the sensor data
+    ///
+    /// The segments are reconstructed in parallel with the `parallel` feature
+    pub(super) fn reconstruct(&mut self) {
+        // no sensor data yet, e.g. before the first frame: the estimates are left unchanged
+        if self.data.is_empty() {
+            return;
+        }
+        if self.kernels.is_empty() {
+            self.build_kernels();
+        }
+        let data = self.data.as_slice();
+        let (bad_slopes, threshold) = (self.bad_slopes, self.slope_threshold);
+        #[cfg(feature = "parallel")]
+        self.kernels
+            .par_iter_mut()
+            .for_each(|kernel| kernel.reconstruct(data, bad_slopes, threshold));
+        #[cfg(not(feature = "parallel"))]
+        self.kernels
+            .iter_mut()
+            .for_each(|kernel| kernel.reconstruct(data, bad_slopes, threshold));
+
+        self.rejected_slopes.clear();
+        self.rejected_slopes
+            .extend(self.kernels.iter().map(|kernel| kernel.n_bad));
+        // the estimates are updated in place unless they are still shared
+        for (k, estimate) in self.estimates.iter_mut().enumerate() {
+            let mut estimate = Arc::make_mut(estimate).iter_mut();
+            for kernel in &self.kernels {
+                for (j, x) in kernel.layout[k].iter().zip(estimate.by_ref()) {
+                    *x = j.map_or(0., |j| kernel.estimate[j]);
+                }
+            }
+        }
+        if let (MaskedModes::Zeroed, Some(mask)) = (self.masked_modes, &self.mode_mask) {
+            self.estimates
+                .iter_mut()
+                .flat_map(|estimate| Arc::make_mut(estimate).iter_mut())
+                .zip(mask)
+                .filter(|(_, masked)| **masked)
+                .for_each(|(x, _)| *x = 0.);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use faer::{Mat, MatRef};
+use gmt_dos_clients_crseo::calibration::{CalibrationMode, Modality, algebra::CalibProps};
+use interface::{Data, Read, UID};
+
+use super::{MergeError, MergeReconstructor, inversion};
+
+/// Mask of the modes of a [MergeReconstructor]
+///
+/// The mask follows the layout of the [MergedEstimate](super::MergedEstimate),
+/// `true` masks the mode out
+#[derive(UID)]
+#[uid(data = Vec<bool>)]
+pub enum ModeMask {}
+
+/// Handling of the masked modes of a [MergeReconstructor]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaskedModes {
+    /// the estimates of the masked modes are set to zero
+    #[default]
+    Zeroed,
+    /// the calibrations are inverted again without the columns of the masked modes
+    ///
+    /// The reduced calibrations are inverted with the Tikhonov regularization if any,
+    /// the SVD truncation and the slope noise covariance are ignored
+    Removed,
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Sets how the masked modes are handled
+    pub fn masked_modes(&mut self, masked_modes: MaskedModes) -> &mut Self {
+        self.masked_modes = masked_modes;
+        self.kernels.clear();
+        self.masked_pinv = match (masked_modes, self.mode_mask.clone()) {
+            (MaskedModes::Removed, Some(mask)) => Some(self.reduced_pinv(&mask)),
+            _ => None,
+        };
+        self
+    }
+    /// Masks out modes at runtime
+    ///
+    /// The mask follows the layout of the [MergedEstimate](super::MergedEstimate),
+    /// `true` masks the mode out.
+    /// The calibrations are inverted again only if the mask differs from the current one
+    pub fn set_mode_mask(&mut self, mask: Vec<bool>) -> Result<&mut Self, MergeError> {
+        let n: usize = self.estimate_sizes.iter().sum();
+        if mask.len() != n {
+            return Err(MergeError::ModeMask {
+                expected: n,
+                found: mask.len(),
+            });
+        }
+        let mask = mask.iter().any(|&masked| masked).then_some(mask);
+        if mask == self.mode_mask {
+            return Ok(self);
+        }
+        match mask {
+            Some(mask) => {
+                self.mode_mask = Some(mask);
+                Ok(self.masked_modes(self.masked_modes))
+            }
+            None => Ok(self.clear_mode_mask()),
+        }
+    }
+    /// Unmasks all the modes
+    pub fn clear_mode_mask(&mut self) -> &mut Self {
+        self.mode_mask = None;
+        self.masked_pinv = None;
+        self.kernels.clear();
+        self
+    }
+    /// Columns of the calibration of each segment that are masked out
+    pub(super) fn masked_columns(&self, mask: &[bool]) -> Vec<Vec<bool>> {
+        let mut columns: Vec<Vec<bool>> = self
+            .blocks
+            .iter()
+            .map(|blocks| vec![false; blocks.iter().map(|(_, n)| n).sum()])
+            .collect();
+        let mut mask = mask.iter();
+        for k in 0..self.n_block() {
+            for (blocks, columns) in self.blocks.iter().zip(columns.iter_mut()) {
+                let offset: usize = blocks[..k].iter().map(|(_, n)| n).sum();
+                let (mode, n) = &blocks[k];
+                // the column # (from 1) of each mode of the estimate, 0 if the mode is not calibrated
+                for j in mode.fill((1..=*n).map(|j| j as f64)) {
+                    if mask.next().is_some_and(|&masked| masked) && j > 0. {
+                        columns[offset + j as usize - 1] = true;
+                    }
+                }
+            }
+        }
+        columns
+    }
+    /// Pseudo-inverses of the calibrations without the masked columns
+    fn reduced_pinv(&mut self, mask: &[bool]) -> Vec<Mat<f64>> {
+        let masked_columns = self.masked_columns(mask);
+        let regularization = self.regularization.clone();
+        self.recon
+            .calib_pinv()
+            .zip(&self.blocks)
+            .zip(&self.norms)
+            .zip(masked_columns)
+            .map(|((((c, _), blocks), norms), masked)| {
+                let (gamma, scale) =
+                    inversion::column_weights(blocks, norms, regularization.as_ref());
+                let c = MatRef::from_column_major_slice(c.as_slice(), c.n_rows(), c.n_cols());
+                inversion::reduced(c, &gamma, &scale, &vec![false; c.nrows()], &masked)
+            })
+            .collect()
+    }
+}
+
+impl<A, B> Read<ModeMask> for MergeReconstructor<CalibrationMode, A, B> {
+    fn read(&mut self, data: Data<ModeMask>) {
+        if let Err(e) = self.set_mode_mask(Vec::clone(&data.into_arc())) {
+            log::warn!("mode mask ignored: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use gmt_dos_clients_io::optics::SensorData;
+    use interface::{Update, Write};
+
+    use super::*;
+    use crate::{
+        MergeReconstructorBuilder, MergedEstimate,
+        fixtures::{N_SEGMENT, calibrations, pickle, random, sensor},
+    };
+
+    const N_SLOPE: usize = 20;
+    const N_MODE: usize = 4;
+
+    type Merge = MergeReconstructor<CalibrationMode>;
+
+    /// Reconstructor of random calibrations, random modes and their slopes
+    fn synthetic(name: &str) -> Result<(Merge, Vec<f64>, Vec<f64>), Box<dyn Error>> {
+        let calibs = calibrations(N_SLOPE, N_MODE, 1);
+        let modes = random(N_SEGMENT * N_MODE, 42);
+        let slopes = sensor(&calibs, &modes);
+        let path = pickle(&format!("gmt-ns-im_mode_mask_{name}.pkl"), calibs)?;
+        let recon = MergeReconstructorBuilder::default()
+            .calibration(path)
+            .build()?;
+        Ok((recon, modes, slopes))
+    }
+
+    fn reconstruct(recon: &mut Merge, slopes: &[f64]) -> Vec<f64> {
+        <_ as Read<SensorData>>::read(recon, Data::new(slopes.to_vec()));
+        recon.update();
+        <_ as Write<MergedEstimate>>::write(recon).unwrap().to_vec()
+    }
+
+    fn assert_close(estimate: &[f64], expected: &[f64]) {
+        for (k, (e, x)) in estimate.iter().zip(expected).enumerate() {
+            assert!((e - x).abs() < 1e-9, "mode #{k}: {e} instead of {x}");
+        }
+    }
+
+    fn mask() -> Vec<bool> {
+        (0..N_SEGMENT * N_MODE).map(|k| k % 3 == 0).collect()
+    }
+
+    #[test]
+    fn masking() -> Result<(), Box<dyn Error>> {
+        let (mut recon, modes, slopes) = synthetic("masking")?;
+        let mask = mask();
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(mask.clone()));
+        let masked: Vec<f64> = modes
+            .iter()
+            .zip(&mask)
+            .map(|(x, &m)| if m { 0. } else { *x })
+            .collect();
+        assert_close(&reconstruct(&mut recon, &slopes), &masked);
+
+        // the masked modes are removed from the calibrations of modes that are not present
+        recon.masked_modes(MaskedModes::Removed);
+        let slopes = sensor(&calibrations(N_SLOPE, N_MODE, 1), &masked);
+        assert_close(&reconstruct(&mut recon, &slopes), &masked);
+        // the same mask does not invert the calibrations again
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(mask));
+        assert!(!recon.kernels.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unmasking() -> Result<(), Box<dyn Error>> {
+        let (mut recon, modes, slopes) = synthetic("unmasking")?;
+        recon.masked_modes(MaskedModes::Removed);
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(mask()));
+        reconstruct(&mut recon, &slopes);
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(vec![false; N_SEGMENT * N_MODE]));
+        assert!(recon.mode_mask.is_none() && recon.masked_pinv.is_none());
+        assert_close(&reconstruct(&mut recon, &slopes), &modes);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_length() -> Result<(), Box<dyn Error>> {
+        let (mut recon, _, _) = synthetic("invalid_length")?;
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(mask()));
+        let n = N_SEGMENT * N_MODE;
+        assert!(matches!(
+            recon.set_mode_mask(vec![true; n + 1]),
+            Err(MergeError::ModeMask { expected, found }) if expected == n && found == n + 1
+        ));
+        // an invalid mask is ignored
+        <_ as Read<ModeMask>>::read(&mut recon, Data::new(vec![true; n + 1]));
+        assert_eq!(recon.mode_mask, Some(mask()));
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read as _, Write as _},
+    path::Path,
+    sync::Arc,
+};
+
+use gmt_dos_clients_crseo::calibration::CalibrationMode;
+use sha2::{Digest, Sha256};
+
+use super::{MergeError, MergeReconstructor, MergeReconstructorBuilder, NoiseCovariance};
+
+/// Binary format magic bytes
+const MAGIC: &[u8; 8] = b"GMTMERGE";
+/// Binary format version
+const VERSION: u32 = 2;
+
+pub(super) fn noise_sha256(noise: &NoiseCovariance) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(noise).unwrap_or_default());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl<A, B> MergeReconstructor<CalibrationMode, A, B> {
+    /// Saves the reconstructor
+    ///
+    /// The reconstructor is pickled if the file extension is `pkl`,
+    /// otherwise it is written in the binary format:
+    ///  * 8 bytes magic: `GMTMERGE`,
+    ///  * format version: `u32` little-endian (2),
+    ///  * [bincode] (v1, little-endian, fixed-size integers) serialization of the reconstructor:
+    ///    the per-segment calibrations and pseudo-inverses, the calibration modes and sizes of the blocks,
+    ///    the paths and SHA-256 hashes of the source calibrations and the inversion settings.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), MergeError> {
+        let path = path.as_ref();
+        let mut file = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|ext| ext == "pkl") {
+            serde_pickle::to_writer(&mut file, self, Default::default())?;
+        } else {
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            bincode::serialize_into(&mut file, self)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+    /// Loads a reconstructor saved with [MergeReconstructor::save]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MergeError> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(File::open(path)?);
+        let mut recon: Self = if path.extension().is_some_and(|ext| ext == "pkl") {
+            serde_pickle::from_reader(&mut file, Default::default())?
+        } else {
+            let mut magic = [0u8; 8];
+            let mut version = [0u8; 4];
+            file.read_exact(&mut magic)?;
+            file.read_exact(&mut version)?;
+            if &magic != MAGIC || u32::from_le_bytes(version) != VERSION {
+                return Err(MergeError::Format(path.to_path_buf()));
+            }
+            bincode::deserialize_from(&mut file)?
+        };
+        recon.estimates = recon
+            .estimate_sizes
+            .iter()
+            .map(|&n| Arc::new(vec![0f64; n]))
+            .collect();
+        Ok(recon)
+    }
+    /// Checks if the reconstructor was built from the same calibrations and with the same settings
+    pub fn is_built_from(&self, builder: &MergeReconstructorBuilder<A, B>) -> bool {
+        self.calibrations == builder.calibrations
+            && builder
+                .calibrations
+                .iter()
+                .zip(&self.sha256)
+                .all(|(path, sha256)| {
+                    crate::manifest::sha256(path).is_ok_and(|hash| hash == *sha256)
+                })
+            && self.svd_truncation == builder.svd_truncation
+            && self.regularization == builder.regularization
+            && self.noise.as_ref().map(noise_sha256) == builder.noise.as_ref().map(noise_sha256)
+    }
+}
+
+impl<A, B> MergeReconstructorBuilder<A, B> {
+    /// Loads the reconstructor saved in `path` if it was built from the same calibrations
+    /// and with the same settings, otherwise builds it and saves it in `path`
+    pub fn build_cached(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<MergeReconstructor<CalibrationMode, A, B>, MergeError> {
+        let path = path.as_ref();
+        if path.exists() {
+            match MergeReconstructor::load(path) {
+                Ok(recon) if recon.is_built_from(&self) => return Ok(recon),
+                Ok(_) => {
+                    log::info!("{path:?} is out of date, rebuilding the merge reconstructor")
+                }
+                Err(e) => {
+                    log::warn!("failed to load {path:?} ({e}), rebuilding the merge reconstructor")
+                }
+            }
+        }
+        let recon = self.build()?;
+        recon.save(path)?;
+        Ok(recon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, path::PathBuf};
+
+    use gmt_dos_clients_io::optics::SensorData;
+    use interface::{Data, Read, Update, Write};
+
+    use super::*;
+    use crate::{
+        MergedEstimate,
+        fixtures::{N_SEGMENT, calibrations, pickle, random},
+    };
+
+    const N_SLOPE: usize = 20;
+
+    type Merge = MergeReconstructor<CalibrationMode>;
+
+    fn builder(name: &str) -> Result<MergeReconstructorBuilder<(), ()>, Box<dyn Error>> {
+        let a = pickle(
+            &format!("gmt-ns-im_persistence_{name}_a.pkl"),
+            calibrations(N_SLOPE, 2, 1),
+        )?;
+        let b = pickle(
+            &format!("gmt-ns-im_persistence_{name}_b.pkl"),
+            calibrations(N_SLOPE, 3, 10),
+        )?;
+        Ok(MergeReconstructorBuilder::default().calibrations([a, b]))
+    }
+
+    fn reconstruct(recon: &mut Merge, slopes: &[f64]) -> Vec<f64> {
+        <_ as Read<SensorData>>::read(recon, Data::new(slopes.to_vec()));
+        recon.update();
+        <_ as Write<MergedEstimate>>::write(recon).unwrap().to_vec()
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn Error>> {
+        let builder = builder("round_trip")?;
+        let mut recon = builder.clone().build()?;
+        let slopes = random(N_SEGMENT * N_SLOPE, 42);
+        let estimate = reconstruct(&mut recon, &slopes);
+        for ext in ["bin", "pkl"] {
+            let path = std::env::temp_dir().join(format!("gmt-ns-im_persistence_round_trip.{ext}"));
+            recon.save(&path)?;
+            let mut loaded = Merge::load(&path)?;
+            assert_eq!(loaded.estimate_sizes(), recon.estimate_sizes());
+            assert!(loaded.is_built_from(&builder));
+            assert_eq!(reconstruct(&mut loaded, &slopes), estimate, "{ext}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cache_invalidation() -> Result<(), Box<dyn Error>> {
+        let builder = builder("cache_invalidation")?;
+        let path = std::env::temp_dir().join("gmt-ns-im_persistence_cache.bin");
+        let _ = std::fs::remove_file(&path);
+        builder.clone().build_cached(&path)?;
+        assert!(Merge::load(&path)?.is_built_from(&builder));
+
+        // other settings
+        let truncated = builder.clone().svd_truncation(vec![1; N_SEGMENT]);
+        assert!(!Merge::load(&path)?.is_built_from(&truncated));
+
+        // modified calibration
+        let b: &PathBuf = &builder.calibrations[1];
+        pickle(
+            b.file_name().unwrap().to_str().unwrap(),
+            calibrations(N_SLOPE, 3, 20),
+        )?;
+        assert!(!Merge::load(&path)?.is_built_from(&builder));
+        builder.clone().build_cached(&path)?;
+        assert!(Merge::load(&path)?.is_built_from(&builder));
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+/*!
+# Noise propagation
+
+For uncorrelated slopes with the same noise variance `σ²`, the covariance of the estimates
+of a reconstructor `R` is `σ² R Rᵀ`.
+The noise propagation coefficient of a mode is the corresponding diagonal element of `R Rᵀ`
+and the expected RMS of its estimate is `σ` times the square root of the coefficient.
+
+The coefficients are computed for the [MergeReconstructor](crate::MergeReconstructor)
+with `MergeReconstructor::noise_propagation` and for the [Reconstructor] pickles with
+[NoisePropagation::from_pickle].
+*/
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use faer::{Mat, MatRef};
+use gmt_dos_clients_crseo::calibration::{Calib, CalibrationMode, ClosedLoopCalib, Reconstructor};
+use serde::Serialize;
+
+use crate::MergeError;
+
+/// Noise propagation coefficients of a segment
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentNoise {
+    /// segment # in [1,7]
+    pub segment: usize,
+    /// block (calibration) index of each mode
+    pub blocks: Vec<usize>,
+    /// noise propagation coefficient of each mode
+    pub coefficients: Vec<f64>,
+}
+
+impl SegmentNoise {
+    /// Trace of the estimate covariance for unit slope noise
+    pub fn trace(&self) -> f64 {
+        self.coefficients.iter().sum()
+    }
+    /// Expected RMS of the estimate of each mode for a given centroid noise RMS
+    pub fn rms(&self, centroid_noise: f64) -> Vec<f64> {
+        self.coefficients
+            .iter()
+            .map(|c| centroid_noise * c.sqrt())
+            .collect()
+    }
+}
+
+/// Noise propagation coefficients of a reconstructor
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NoisePropagation {
+    pub segments: Vec<SegmentNoise>,
+}
+
+impl NoisePropagation {
+    /// Computes the coefficients from the per-segment pseudo-inverses
+    ///
+    /// `blocks` gives the number of modes of each block for each segment,
+    /// all the modes are in the same block if it is `None`
+    pub fn new<'a>(
+        pinvs: impl IntoIterator<Item = MatRef<'a, f64>>,
+        blocks: Option<&[Vec<usize>]>,
+    ) -> Self {
+        let segments = pinvs
+            .into_iter()
+            .enumerate()
+            .map(|(i, pinv)| {
+                let coefficients: Vec<f64> = (0..pinv.nrows())
+                    .map(|r| (0..pinv.ncols()).map(|c| pinv[(r, c)].powi(2)).sum())
+                    .collect();
+                let blocks = blocks.and_then(|blocks| blocks.get(i)).map_or_else(
+                    || vec![0; coefficients.len()],
+                    |sizes| {
+                        sizes
+                            .iter()
+                            .enumerate()
+                            .flat_map(|(k, &n)| vec![k; n])
+                            .collect()
+                    },
+                );
+                SegmentNoise {
+                    segment: i + 1,
+                    blocks,
+                    coefficients,
+                }
+            })
+            .collect();
+        Self { segments }
+    }
+    /// Computes the coefficients of a closed-loop or open-loop [Reconstructor] pickle
+    pub fn from_pickle(path: impl AsRef<Path>) -> Result<Self, MergeError> {
+        let path = path.as_ref();
+        let closed_loop: Result<Reconstructor<CalibrationMode, ClosedLoopCalib>, _> =
+            serde_pickle::from_reader(File::open(path)?, Default::default());
+        let pinvs: Vec<Mat<f64>> = match closed_loop {
+            Ok(mut recon) => recon
+                .calib_pinv()
+                .map(|(_, ic)| ic.as_ref().to_owned())
+                .collect(),
+            Err(_) => {
+                let mut recon: Reconstructor<CalibrationMode, Calib<CalibrationMode>> =
+                    serde_pickle::from_reader(File::open(path)?, Default::default())?;
+                recon
+                    .calib_pinv()
+                    .map(|(_, ic)| ic.as_ref().to_owned())
+                    .collect()
+            }
+        };
+        Ok(Self::new(pinvs.iter().map(|pinv| pinv.as_ref()), None))
+    }
+    /// Writes the table of the coefficients to a CSV file
+    ///
+    /// The expected RMS of the estimates is added if the centroid noise RMS is given
+    pub fn to_csv(&self, path: impl AsRef<Path>, centroid_noise: Option<f64>) -> io::Result<()> {
+        let mut file = File::create(path.as_ref())?;
+        write!(file, "segment,block,mode,coefficient")?;
+        if centroid_noise.is_some() {
+            write!(file, ",rms")?;
+        }
+        writeln!(file)?;
+        for s in &self.segments {
+            // mode index within the block
+            let mut mode = 0;
+            for (i, (block, coefficient)) in s.blocks.iter().zip(&s.coefficients).enumerate() {
+                if i > 0 && s.blocks[i - 1] != *block {
+                    mode = 0;
+                }
+                write!(file, "{},{block},{mode},{coefficient:e}", s.segment)?;
+                mode += 1;
+                if let Some(sigma) = centroid_noise {
+                    write!(file, ",{:e}", sigma * coefficient.sqrt())?;
+                }
+                writeln!(file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for NoisePropagation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Noise propagation (trace of R Rᵀ per segment):")?;
+        for s in &self.segments {
+            writeln!(
+                f,
+                " * segment #{}: {:.3e} ({} modes)",
+                s.segment,
+                s.trace(),
+                s.coefficients.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, path::PathBuf};
+
+    use gmt_dos_clients_crseo::calibration::algebra::CalibProps;
+
+    use super::*;
+    use crate::{
+        MergeReconstructorBuilder,
+        fixtures::{N_SEGMENT, calibrations, pickle},
+    };
+
+    fn close(x: f64, y: f64) -> bool {
+        (x - y).abs() < 1e-12 * y.abs().max(1.)
+    }
+
+    #[test]
+    fn coefficients() {
+        let pinv = Mat::from_fn(2, 3, |i, j| [[1., 2., 0.], [0., -1., 3.]][i][j]);
+        let noise = NoisePropagation::new([pinv.as_ref()], Some(&[vec![1, 1]]));
+        let s = &noise.segments[0];
+        assert_eq!((s.segment, s.blocks.clone()), (1, vec![0, 1]));
+        // row sums of the squared pseudo-inverse
+        assert_eq!(s.coefficients, vec![5., 10.]);
+        assert_eq!(s.trace(), 15.);
+        let rms = s.rms(0.5);
+        assert!(close(rms[0], 0.5 * 5f64.sqrt()) && close(rms[1], 0.5 * 10f64.sqrt()));
+        let noise = NoisePropagation::new([pinv.as_ref()], None);
+        assert_eq!(noise.segments[0].blocks, vec![0, 0]);
+    }
+
+    #[test]
+    fn csv() -> Result<(), Box<dyn Error>> {
+        let pinv = Mat::from_fn(3, 1, |i, _| [1., 2., 3.][i]);
+        let noise = NoisePropagation::new([pinv.as_ref()], Some(&[vec![2, 1]]));
+        let path = std::env::temp_dir().join("gmt-ns-im_noise_propagation.csv");
+        noise.to_csv(&path, Some(2.))?;
+        let csv = std::fs::read_to_string(&path)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "segment,block,mode,coefficient,rms");
+        // the mode index restarts at each block
+        for (line, (block, mode, coefficient)) in
+            lines[1..].iter().zip([(0, 0, 1.), (0, 1, 4.), (1, 0, 9.)])
+        {
+            let fields: Vec<&str> = line.split(',').collect();
+            assert_eq!(fields[..3].join(","), format!("1,{block},{mode}"));
+            assert_eq!(fields[3].parse::<f64>()?, coefficient);
+            assert!(close(fields[4].parse()?, 2. * coefficient.sqrt()));
+        }
+        assert_eq!(lines.len(), 4);
+        noise.to_csv(&path, None)?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?.lines().next(),
+            Some("segment,block,mode,coefficient")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pickle_coefficients() -> Result<(), Box<dyn Error>> {
+        let (n_slope, n_mode) = (10, 2);
+        let calibs = calibrations(n_slope, n_mode, 1);
+        let path = pickle("gmt-ns-im_noise_propagation.pkl", calibs.clone())?;
+        let noise = NoisePropagation::from_pickle(&path)?;
+        assert_eq!(noise.segments.len(), N_SEGMENT);
+        for (s, calib) in noise.segments.iter().zip(&calibs) {
+            // R Rᵀ = (CᵀC)⁻¹ for the least-squares reconstructor R = (CᵀC)⁻¹Cᵀ
+            let c = calib.as_slice();
+            let dot = |a: usize, b: usize| {
+                (0..n_slope)
+                    .map(|r| c[r + a * n_slope] * c[r + b * n_slope])
+                    .sum::<f64>()
+            };
+            let det = dot(0, 0) * dot(1, 1) - dot(0, 1).powi(2);
+            let expected = [dot(1, 1) / det, dot(0, 0) / det];
+            assert!(
+                s.coefficients
+                    .iter()
+                    .zip(expected)
+                    .all(|(x, e)| (x - e).abs() < 1e-9 * e),
+                "{:?} != {expected:?}",
+                s.coefficients
+            );
+        }
+        Ok(())
+    }
+
+    /// Pickles one-mode calibrations, the calibration of each segment is `column`
+    fn synthetic(name: &str, column: [f64; 4]) -> PathBuf {
+        let calibs = (0..N_SEGMENT)
+            .map(|i| {
+                let mask: Vec<bool> = (0..N_SEGMENT * 4).map(|k| k / 4 == i).collect();
+                Calib::builder()
+                    .c(column.to_vec())
+                    .n_cols(1)
+                    .mask(mask)
+                    .mode(CalibrationMode::modes(1, 1e-6))
+                    .build()
+            })
+            .collect();
+        pickle(&format!("gmt-ns-im_noise_propagation_{name}.pkl"), calibs).unwrap()
+    }
+
+    #[test]
+    fn merge() -> Result<(), Box<dyn Error>> {
+        let a = synthetic("a", [1., 0., 0., 0.]);
+        let b = synthetic("b", [1.2, 1.6, 0., 0.]);
+        let mut recon = MergeReconstructorBuilder::<(), ()>::default()
+            .calibrations([a, b])
+            .build()?;
+        let noise = recon.noise_propagation();
+        assert_eq!(noise.segments.len(), N_SEGMENT);
+        // (CᵀC)⁻¹ with CᵀC = [[1,1.2],[1.2,4]], independent of the normalization of the blocks
+        for s in &noise.segments {
+            assert_eq!(s.blocks, vec![0, 1]);
+            assert!(close(s.coefficients[0], 4. / 2.56), "{:?}", s.coefficients);
+            assert!(close(s.coefficients[1], 1. / 2.56), "{:?}", s.coefficients);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+/*!
+# Run provenance
+
+A [Provenance] record is written alongside the outputs of each run of the model (`run.json`).
+It gathers everything needed to trace the outputs back to the model that produced them:
+the FEM and mount model, the git revision, the effective configuration and scenario,
+the hashes of the calibration artifacts, the description of the main model components
+and the wall-clock timings.
+*/
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    SimConfig,
+    manifest::{self, Manifest},
+    scenario::Scenario,
+};
+
+#[derive(Debug)]
+pub enum ProvenanceError {
+    Open(io::Error),
+    Json(serde_json::Error),
+}
+
+impl Display for ProvenanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProvenanceError::Open(error) => error.fmt(f),
+            ProvenanceError::Json(error) => error.fmt(f),
+        }
+    }
+}
+impl Error for ProvenanceError {}
+impl From<io::Error> for ProvenanceError {
+    fn from(value: io::Error) -> Self {
+        Self::Open(value)
+    }
+}
+impl From<serde_json::Error> for ProvenanceError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+/// Calibration artifact or model input hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputHash {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+}
+
+/// Run provenance record
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Provenance {
+    /// `FEM_REPO` the model was built with
+    pub fem_repo: String,
+    /// `MOUNT_MODEL` the model was built with
+    pub mount_model: String,
+    /// git revision of the model
+    pub git_revision: String,
+    /// command line arguments
+    pub args: Vec<String>,
+    /// run start date and time (RFC 3339)
+    pub start: String,
+    /// run end date and time (RFC 3339)
+    pub end: Option<String>,
+    pub config: Option<SimConfig>,
+    pub scenario: Option<Scenario>,
+    /// hashes of the calibration artifacts and of the other model inputs
+    pub inputs: Vec<InputHash>,
+    /// description of the model components
+    pub components: BTreeMap<String, String>,
+    /// wall-clock timings [s]
+    pub timings: Vec<(String, f64)>,
+    /// files written by the model
+    pub outputs: Vec<PathBuf>,
+    /// error that ended the run if any
+    pub error: Option<String>,
+}
+
+impl Provenance {
+    /// Starts a new record
+    pub fn new(
+        fem_repo: impl Into<String>,
+        mount_model: impl Into<String>,
+        git_revision: impl Into<String>,
+    ) -> Self {
+        Self {
+            fem_repo: fem_repo.into(),
+            mount_model: mount_model.into(),
+            git_revision: git_revision.into(),
+            args: std::env::args().collect(),
+            start: chrono::Local::now().to_rfc3339(),
+            ..Default::default()
+        }
+    }
+    /// Records the effective simulation configuration
+    pub fn config(&mut self, sim_config: &SimConfig) -> &mut Self {
+        self.config = Some(sim_config.clone());
+        self
+    }
+    /// Records the perturbation scenario
+    pub fn scenario(&mut self, scenario: &Scenario) -> &mut Self {
+        self.scenario = Some(scenario.clone());
+        self
+    }
+    /// Records the hashes of the calibration artifacts of the manifest
+    pub fn inputs(&mut self, manifest: &Manifest) -> &mut Self {
+        self.inputs = manifest
+            .hashes()
+            .into_iter()
+            .map(|(path, hash)| InputHash {
+                path: path.to_path_buf(),
+                sha256: hash.ok(),
+            })
+            .collect();
+        self
+    }
+    /// Records the hash of a model input, replacing the hash recorded for the same file if any
+    pub fn input(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let path = path.as_ref();
+        let input = InputHash {
+            path: path.to_path_buf(),
+            sha256: manifest::sha256(path).ok(),
+        };
+        match self
+            .inputs
+            .iter_mut()
+            .find(|recorded| recorded.path == path)
+        {
+            Some(recorded) => *recorded = input,
+            None => self.inputs.push(input),
+        }
+        self
+    }
+    /// Records the description of a model component
+    pub fn component(&mut self, name: impl Into<String>, component: &impl Display) -> &mut Self {
+        self.components.insert(name.into(), component.to_string());
+        self
+    }
+    /// Records the wall-clock time of a stage of the run
+    pub fn timing(&mut self, stage: impl Into<String>, elapsed: Duration) -> &mut Self {
+        self.timings.push((stage.into(), elapsed.as_secs_f64()));
+        self
+    }
+    /// Records a file written by the model
+    pub fn output(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.outputs.push(path.as_ref().to_path_buf());
+        self
+    }
+    /// Records the end of the run and the error that ended it if any
+    pub fn end<T, E: Display>(&mut self, result: &Result<T, E>) -> &mut Self {
+        self.end = Some(chrono::Local::now().to_rfc3339());
+        self.error = result.as_ref().err().map(|e| e.to_string());
+        self
+    }
+    /// Writes the record to a JSON file
+    pub fn to_json(&self, path: impl AsRef<Path>) -> Result<(), ProvenanceError> {
+        serde_json::to_writer_pretty(File::create(path.as_ref())?, self)?;
+        Ok(())
+    }
+    /// Reads a record from a JSON file
+    pub fn from_json(path: impl AsRef<Path>) -> Result<Self, ProvenanceError> {
+        Ok(serde_json::from_reader(File::open(path.as_ref())?)?)
+    }
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Run provenance:")?;
+        writeln!(f, " * FEM         : {}", self.fem_repo)?;
+        writeln!(f, " * MOUNT       : {}", self.mount_model)?;
+        writeln!(f, " * git revision: {}", self.git_revision)?;
+        writeln!(f, " * command     : {}", self.args.join(" "))?;
+        writeln!(
+            f,
+            " * run         : {} -> {}",
+            self.start,
+            self.end.as_deref().unwrap_or("?")
+        )?;
+        for (stage, elapsed) in &self.timings {
+            writeln!(f, "   * {stage}: {elapsed:.1}s")?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, " * error       : {error}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn Error>> {
+        let input = std::env::temp_dir().join("gmt-ns-im_provenance_input.txt");
+        std::fs::write(&input, "input")?;
+        let scenario: Scenario = toml::from_str(
+            r#"
+            [[mount]]
+            dof = 1
+            signal = { kind = "step", amplitude = 1e-6 }
+            "#,
+        )?;
+        let mut record = Provenance::new("fem", "mount", "0123abcd");
+        record
+            .config(&SimConfig::default())
+            .scenario(&scenario)
+            .input(&input)
+            .timing("bootstrapping", Duration::from_millis(1500))
+            .timing("closed loop", Duration::from_millis(250))
+            .output("data/on-axis_wfe.parquet")
+            .end(&Ok::<(), String>(()));
+        let path = std::env::temp_dir().join("gmt-ns-im_provenance_run.json");
+        record.to_json(&path)?;
+        let read = Provenance::from_json(&path)?;
+        assert_eq!(serde_json::to_value(&read)?, serde_json::to_value(&record)?);
+        assert_eq!(read.inputs.len(), 1);
+        assert_eq!(read.inputs[0].sha256, Some(manifest::sha256(&input)?));
+        assert_eq!(read.timings[0], ("bootstrapping".to_string(), 1.5));
+        assert_eq!(read.scenario.map(|s| s.mount.len()), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn input_replacement() -> Result<(), Box<dyn Error>> {
+        let input = std::env::temp_dir().join("gmt-ns-im_provenance_replaced.txt");
+        std::fs::write(&input, "before")?;
+        let mut record = Provenance::default();
+        record.input(&input);
+        let before = record.inputs[0].sha256.clone();
+        std::fs::write(&input, "after")?;
+        record.input(&input).input("missing.bin");
+        assert_eq!(record.inputs.len(), 2);
+        assert_eq!(record.inputs[0].path, input);
+        assert_ne!(record.inputs[0].sha256, before);
+        assert_eq!(record.inputs[0].sha256, Some(manifest::sha256(&input)?));
+        assert_eq!(record.inputs[1].sha256, None);
+        Ok(())
+    }
+}
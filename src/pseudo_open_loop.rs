@@ -1,35 +1,161 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, marker::PhantomData, sync::Arc};
 
-use gmt_dos_clients_crseo::calibration::{Reconstructor, algebra::CalibProps};
-use gmt_dos_clients_io::{gmt_m2::M2RigidBodyMotions, optics::SensorData};
-use interface::{Data, Read, UID, Update, Write};
+use gmt_dos_clients_crseo::calibration::{Modality, Reconstructor, algebra::CalibProps};
+use gmt_dos_clients_io::{
+    gmt_m1::M1RigidBodyMotions,
+    gmt_m2::M2RigidBodyMotions,
+    mount::MountSetPoint,
+    optics::{M1Modes, SensorData},
+};
+use interface::{Data, Read, UID, UniqueIdentifier, Update, Write};
 
 #[derive(UID)]
 pub enum PseudoSensorData {}
 
-#[derive(Debug, Default)]
-pub struct PseudoOpenLoop {
+/// Pseudo open-loop sensor data
+///
+/// Adds to the sensor data the contribution of the command `U`,
+/// i.e. the product of the calibration of each segment with its command.
+/// The command is split between the calibrations of the [Reconstructor] in order,
+/// the size of the command of a calibration is given by its calibration mode.
+///
+/// The contributions of several commands are summed by chaining pseudo open-loops,
+/// the [PseudoSensorData] of one being the input of the next one.
+///
+/// The commands are kept in a ring buffer and the contribution is computed with the average
+/// of the commands over the sensor integration window, shifted by the sensor latency
+/// (see [PseudoOpenLoop::integration] and [PseudoOpenLoop::latency]).
+/// Commands which size differs from [PseudoOpenLoop::command_size] are ignored.
+#[derive(Debug)]
+pub struct PseudoOpenLoop<U = M2RigidBodyMotions> {
     recon: Reconstructor,
-    cmd: Arc<Vec<f64>>,
+    // command index of each column of each calibration
+    columns: Vec<Vec<usize>>,
+    n_command: usize,
+    // number of commands ignored because of their size
+    n_rejected: usize,
+    // latest commands, the newest at the back
+    cmds: VecDeque<Arc<Vec<f64>>>,
+    // number of commands averaged over
+    integration: usize,
+    // number of commands between the end of the integration window and the sensor data
+    latency: usize,
+    sensor_data: Arc<Vec<f64>>,
     slopes: Vec<f64>,
+    u: PhantomData<U>,
 }
 
-impl PseudoOpenLoop {
+impl<U> PseudoOpenLoop<U> {
     pub fn new(recon: Reconstructor) -> Self {
+        let mut offset = 0;
+        let columns = recon
+            .calib()
+            .map(|calib| {
+                // the column # (from 1) of each entry of the command, 0 if the entry is not calibrated
+                let command = calib.mode().fill((1..=calib.n_cols()).map(|j| j as f64));
+                let mut columns = vec![0; calib.n_cols()];
+                for (k, &j) in command.iter().enumerate() {
+                    if j > 0. {
+                        columns[j as usize - 1] = offset + k;
+                    }
+                }
+                offset += command.len();
+                columns
+            })
+            .collect();
         Self {
             recon,
-            ..Default::default()
+            columns,
+            n_command: offset,
+            n_rejected: 0,
+            cmds: Default::default(),
+            integration: 1,
+            latency: 0,
+            sensor_data: Default::default(),
+            slopes: Default::default(),
+            u: PhantomData,
+        }
+    }
+    /// Sets the number of commands the sensor integrates over (default: 1)
+    ///
+    /// The integration and the [latency](PseudoOpenLoop::latency) are given in commands read,
+    /// i.e. at the rate of the command link: the SH48 integrates over
+    /// [config::agws::sh48::RATE](crate::config::agws::sh48::RATE) commands read at every step
+    /// but over a single command read at the SH48 rate
+    pub fn integration(mut self, n: usize) -> Self {
+        self.integration = n.max(1);
+        self
+    }
+    /// Sets the number of commands read between the end of the sensor integration
+    /// and the sensor data (default: 0)
+    pub fn latency(mut self, n: usize) -> Self {
+        self.latency = n;
+        self
+    }
+    /// Average of the commands over the sensor integration window
+    ///
+    /// Only the available commands are averaged over until the ring buffer is full
+    fn command(&self) -> Vec<f64> {
+        let window: Vec<&Arc<Vec<f64>>> = self
+            .cmds
+            .iter()
+            .rev()
+            .skip(self.latency)
+            .take(self.integration)
+            .collect();
+        let n = self.command_size();
+        let mut cmd = vec![0f64; n];
+        for c in &window {
+            cmd.iter_mut().zip(c.iter()).for_each(|(x, c)| *x += c);
         }
+        let w = window.len().max(1) as f64;
+        cmd.iter_mut().for_each(|x| *x /= w);
+        cmd
+    }
+    /// Size of the command
+    pub fn command_size(&self) -> usize {
+        self.n_command
+    }
+    /// Number of commands ignored because their size differs from [PseudoOpenLoop::command_size]
+    pub fn rejected_commands(&self) -> usize {
+        self.n_rejected
+    }
+    fn push_command(&mut self, cmd: Arc<Vec<f64>>) {
+        if cmd.len() != self.n_command {
+            // reported once, a command of the wrong size is likely to be the same at every step
+            if self.n_rejected == 0 {
+                log::warn!(
+                    "pseudo open-loop: command of size {} ignored, expected {}",
+                    cmd.len(),
+                    self.n_command
+                );
+            }
+            self.n_rejected += 1;
+            return;
+        }
+        if self.cmds.len() == self.integration + self.latency {
+            self.cmds.pop_front();
+        }
+        self.cmds.push_back(cmd);
     }
 }
 
-impl Update for PseudoOpenLoop {
+impl<U: UniqueIdentifier> Update for PseudoOpenLoop<U> {
     fn update(&mut self) {
+        self.slopes = self.sensor_data.to_vec();
+        if self.cmds.is_empty() {
+            return;
+        }
+        let cmd = self.command();
         self.recon
             .calib()
-            .zip(self.cmd.chunks(6))
-            .for_each(|(calib, c)| {
-                let cs = calib * &c[..calib.n_cols()];
+            .zip(&self.columns)
+            .for_each(|(calib, columns)| {
+                let c: Vec<f64> = columns
+                    .iter()
+                    .map(|&k| cmd.get(k).copied().unwrap_or_default())
+                    .collect();
+                let cs = calib * c.as_slice();
                 let mut iter = cs.col_as_slice(0).iter();
                 self.slopes
                     .iter_mut()
@@ -43,17 +169,36 @@ impl Update for PseudoOpenLoop {
     }
 }
 
-impl Read<M2RigidBodyMotions> for PseudoOpenLoop {
-    fn read(&mut self, data: Data<M2RigidBodyMotions>) {
-        self.cmd = data.into_arc();
-    }
+/// Implements [Read] of the commands of a [PseudoOpenLoop]
+macro_rules! impl_read_command {
+    ($($u:ty),*) => {
+        $(
+            impl Read<$u> for PseudoOpenLoop<$u> {
+                fn read(&mut self, data: Data<$u>) {
+                    self.push_command(data.into_arc());
+                }
+            }
+        )*
+    };
 }
-impl Read<SensorData> for PseudoOpenLoop {
+impl_read_command!(
+    M2RigidBodyMotions,
+    M1RigidBodyMotions,
+    M1Modes,
+    MountSetPoint
+);
+
+impl<U: UniqueIdentifier> Read<SensorData> for PseudoOpenLoop<U> {
     fn read(&mut self, data: Data<SensorData>) {
-        self.slopes = data.as_slice().to_vec();
+        self.sensor_data = data.into_arc();
+    }
+}
+impl<U: UniqueIdentifier> Read<PseudoSensorData> for PseudoOpenLoop<U> {
+    fn read(&mut self, data: Data<PseudoSensorData>) {
+        self.sensor_data = data.into_arc();
     }
 }
-impl Write<PseudoSensorData> for PseudoOpenLoop {
+impl<U: UniqueIdentifier> Write<PseudoSensorData> for PseudoOpenLoop<U> {
     fn write(&mut self) -> Option<Data<PseudoSensorData>> {
         Some(self.slopes.clone().into())
     }
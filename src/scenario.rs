@@ -0,0 +1,603 @@
+/*!
+# Perturbation scenarios
+
+A scenario is a TOML file that lists the time histories applied to
+the M1 and M2 rigid body motions, to the M1 bending modes and to the mount set-point:
+```toml
+[[m2_rbm]]
+segment = 1
+dof = 0
+signal = { kind = "step", amplitude = 1e-6 }
+
+[[m1_modes]]
+segment = 7
+dof = 2
+signal = { kind = "sinusoid", amplitude = 1e-7, frequency = 2.0 }
+
+[[mount]]
+dof = 1
+signal = { kind = "noise", rms = 1e-7, cutoff = 5.0, seed = 42 }
+```
+Segments are numbered from 1 to 7 and degrees of freedom (DOF) from 0,
+the mount DOFs are (azimuth, elevation, GIR).
+Times are given in seconds from the start of the simulation,
+perturbations on the same DOF are summed and unknown groups or keys are rejected.
+The signals are sampled on a [TimeBase] that matches the rate they are written at.
+
+Each perturbation group is compiled into a [ScenarioSignals] client
+that is a drop-in replacement for `gmt_dos_clients::signals::Signals`.
+*/
+
+use std::{error::Error, f64::consts::PI, fmt::Display, fs, io, path::Path, sync::Arc};
+
+use interface::{Data, UniqueIdentifier, Update, Write};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Open(io::Error),
+    Toml(toml::de::Error),
+    Segment {
+        group: &'static str,
+        segment: Option<usize>,
+    },
+    Dof {
+        group: &'static str,
+        dof: usize,
+        n_dof: usize,
+    },
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Open(error) => error.fmt(f),
+            ScenarioError::Toml(error) => error.fmt(f),
+            ScenarioError::Segment {
+                group,
+                segment: Some(sid),
+            } => write!(f, "{group}: expected a segment # in [1,7], found {sid}"),
+            ScenarioError::Segment {
+                group,
+                segment: None,
+            } => write!(f, "{group}: missing segment #"),
+            ScenarioError::Dof { group, dof, n_dof } => {
+                write!(
+                    f,
+                    "{group}: expected a DOF in [0,{}], found {dof}",
+                    n_dof - 1
+                )
+            }
+        }
+    }
+}
+impl Error for ScenarioError {}
+impl From<io::Error> for ScenarioError {
+    fn from(value: io::Error) -> Self {
+        Self::Open(value)
+    }
+}
+impl From<toml::de::Error> for ScenarioError {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Toml(value)
+    }
+}
+
+/// Perturbation time history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Waveform {
+    /// Step of a given amplitude starting at `start`
+    Step {
+        amplitude: f64,
+        #[serde(default)]
+        start: f64,
+    },
+    /// Ramp of a given slope [unit/s] starting at `start` and held constant after `start+duration`
+    Ramp {
+        slope: f64,
+        #[serde(default)]
+        start: f64,
+        duration: Option<f64>,
+    },
+    /// Sinusoid of a given frequency [Hz] and phase [rd] starting at `start`
+    Sinusoid {
+        amplitude: f64,
+        frequency: f64,
+        #[serde(default)]
+        phase: f64,
+        #[serde(default)]
+        start: f64,
+    },
+    /// Linear frequency sweep from `f0` to `f1` [Hz] over `duration` starting at `start`
+    Chirp {
+        amplitude: f64,
+        f0: f64,
+        f1: f64,
+        #[serde(default)]
+        start: f64,
+        duration: f64,
+    },
+    /// Gaussian white noise of a given RMS, low-pass filtered if a `cutoff` frequency [Hz] is given
+    Noise {
+        rms: f64,
+        cutoff: Option<f64>,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default)]
+        start: f64,
+    },
+}
+
+/// Time base of the scenario signals
+///
+/// `n_step` samples at `sampling_frequency` [Hz], the first one at `start` [s]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeBase {
+    pub n_step: usize,
+    pub sampling_frequency: f64,
+    pub start: f64,
+}
+
+impl TimeBase {
+    /// `n_step` samples at `sampling_frequency` [Hz] from the start of the simulation
+    pub fn new(n_step: usize, sampling_frequency: f64) -> Self {
+        Self {
+            n_step,
+            sampling_frequency,
+            start: 0.,
+        }
+    }
+    /// Sets the time of the first sample [s]
+    pub fn start(mut self, start: f64) -> Self {
+        self.start = start;
+        self
+    }
+    /// Keeps one sample every `rate` samples,
+    /// for signals written at `rate` times the simulation sampling period
+    pub fn decimate(mut self, rate: usize) -> Self {
+        let rate = rate.max(1);
+        self.n_step = self.n_step.div_ceil(rate);
+        self.sampling_frequency /= rate as f64;
+        self
+    }
+    /// Time of the `i`th sample [s]
+    pub fn time(&self, i: usize) -> f64 {
+        self.start + i as f64 / self.sampling_frequency
+    }
+}
+
+impl Waveform {
+    /// Samples the waveform on the given time base
+    pub fn sample(&self, time_base: TimeBase) -> Vec<f64> {
+        let TimeBase {
+            n_step,
+            sampling_frequency,
+            ..
+        } = time_base;
+        let time = |i: usize| time_base.time(i);
+        match *self {
+            Waveform::Step { amplitude, start } => (0..n_step)
+                .map(|i| if time(i) >= start { amplitude } else { 0. })
+                .collect(),
+            Waveform::Ramp {
+                slope,
+                start,
+                duration,
+            } => (0..n_step)
+                .map(|i| {
+                    let t = (time(i) - start).max(0.);
+                    slope * duration.map_or(t, |d| t.min(d))
+                })
+                .collect(),
+            Waveform::Sinusoid {
+                amplitude,
+                frequency,
+                phase,
+                start,
+            } => (0..n_step)
+                .map(|i| {
+                    let t = time(i) - start;
+                    if t >= 0. {
+                        amplitude * (2. * PI * frequency * t + phase).sin()
+                    } else {
+                        0.
+                    }
+                })
+                .collect(),
+            Waveform::Chirp {
+                amplitude,
+                f0,
+                f1,
+                start,
+                duration,
+            } => (0..n_step)
+                .map(|i| {
+                    let t = time(i) - start;
+                    if (0. ..=duration).contains(&t) {
+                        let k = (f1 - f0) / duration;
+                        amplitude * (2. * PI * (f0 * t + 0.5 * k * t * t)).sin()
+                    } else {
+                        0.
+                    }
+                })
+                .collect(),
+            Waveform::Noise {
+                rms,
+                cutoff,
+                seed,
+                start,
+            } => {
+                let mut rng = Gaussian::new(seed);
+                // 1st order low-pass filter: y[k] = y[k-1] + alpha (x[k] - y[k-1])
+                let alpha = cutoff.map_or(1., |fc| 1. - (-2. * PI * fc / sampling_frequency).exp());
+                // the white noise is scaled for the filtered noise to have the requested RMS
+                let sigma = rms * ((2. - alpha) / alpha).sqrt();
+                let mut y = 0f64;
+                (0..n_step)
+                    .map(|i| {
+                        if time(i) >= start {
+                            y += alpha * (sigma * rng.sample() - y);
+                            y
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Normal distribution sampler (xorshift64* & Box-Muller)
+struct Gaussian {
+    state: u64,
+    spare: Option<f64>,
+}
+impl Gaussian {
+    fn new(seed: u64) -> Self {
+        Self {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+            spare: None,
+        }
+    }
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let x = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        ((x >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+    fn sample(&mut self) -> f64 {
+        if let Some(x) = self.spare.take() {
+            return x;
+        }
+        let r = (-2. * self.uniform().ln()).sqrt();
+        let theta = 2. * PI * self.uniform();
+        self.spare = Some(r * theta.sin());
+        r * theta.cos()
+    }
+}
+
+/// Perturbation applied to a single degree of freedom
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Perturbation {
+    /// segment # in [1,7]
+    pub segment: Option<usize>,
+    /// degree of freedom
+    pub dof: usize,
+    pub signal: Waveform,
+}
+
+/// Perturbation scenario
+///
+/// Any unknown group or key is rejected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// M1 segments rigid body motions (6 DOFs per segment)
+    pub m1_rbm: Vec<Perturbation>,
+    /// M2 segments rigid body motions (6 DOFs per segment)
+    pub m2_rbm: Vec<Perturbation>,
+    /// M1 segments bending modes (`n_mode` DOFs per segment)
+    pub m1_modes: Vec<Perturbation>,
+    /// Mount set-point (azimuth, elevation, GIR)
+    pub mount: Vec<Perturbation>,
+}
+
+impl Scenario {
+    /// Loads a scenario from a TOML file
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let scenario: Self = toml::from_str(&fs::read_to_string(path.as_ref())?)?;
+        Ok(scenario)
+    }
+    /// M1 segments rigid body motions signals
+    pub fn m1_rbm(&self, time_base: TimeBase) -> Result<ScenarioSignals, ScenarioError> {
+        ScenarioSignals::segments("m1_rbm", &self.m1_rbm, 6, time_base)
+    }
+    /// M2 segments rigid body motions signals
+    pub fn m2_rbm(&self, time_base: TimeBase) -> Result<ScenarioSignals, ScenarioError> {
+        ScenarioSignals::segments("m2_rbm", &self.m2_rbm, 6, time_base)
+    }
+    /// M1 segments bending modes signals
+    pub fn m1_modes(
+        &self,
+        n_mode: usize,
+        time_base: TimeBase,
+    ) -> Result<ScenarioSignals, ScenarioError> {
+        ScenarioSignals::segments("m1_modes", &self.m1_modes, n_mode, time_base)
+    }
+    /// Mount set-point signals
+    pub fn mount(&self, time_base: TimeBase) -> Result<ScenarioSignals, ScenarioError> {
+        let mut signals = ScenarioSignals::new(3, time_base.n_step);
+        for p in &self.mount {
+            if p.dof >= 3 {
+                return Err(ScenarioError::Dof {
+                    group: "mount",
+                    dof: p.dof,
+                    n_dof: 3,
+                });
+            }
+            signals.add(p.dof, p.signal.sample(time_base));
+        }
+        Ok(signals)
+    }
+}
+
+/// Scenario signals client
+///
+/// Writes the sum of the perturbations applied to each channel
+/// for `n_step` steps and then returns `None`, ending the model
+#[derive(Debug, Default)]
+pub struct ScenarioSignals {
+    n_channel: usize,
+    n_step: usize,
+    step: usize,
+    channels: Vec<(usize, Vec<f64>)>,
+    data: Option<Arc<Vec<f64>>>,
+}
+
+impl ScenarioSignals {
+    /// Creates a new client with `n_channel` null channels
+    pub fn new(n_channel: usize, n_step: usize) -> Self {
+        Self {
+            n_channel,
+            n_step,
+            ..Default::default()
+        }
+    }
+    fn segments(
+        group: &'static str,
+        perturbations: &[Perturbation],
+        n_dof: usize,
+        time_base: TimeBase,
+    ) -> Result<Self, ScenarioError> {
+        let mut signals = Self::new(7 * n_dof, time_base.n_step);
+        for p in perturbations {
+            let sid = match p.segment {
+                Some(sid @ 1..=7) => sid,
+                segment => return Err(ScenarioError::Segment { group, segment }),
+            };
+            if p.dof >= n_dof {
+                return Err(ScenarioError::Dof {
+                    group,
+                    dof: p.dof,
+                    n_dof,
+                });
+            }
+            signals.add((sid - 1) * n_dof + p.dof, p.signal.sample(time_base));
+        }
+        Ok(signals)
+    }
+    fn add(&mut self, channel: usize, samples: Vec<f64>) {
+        match self.channels.iter_mut().find(|(c, _)| *c == channel) {
+            Some((_, s)) => s.iter_mut().zip(samples).for_each(|(s, x)| *s += x),
+            None => self.channels.push((channel, samples)),
+        }
+    }
+    /// Returns the number of channels
+    pub fn n_channel(&self) -> usize {
+        self.n_channel
+    }
+}
+
+impl Update for ScenarioSignals {
+    fn update(&mut self) {
+        self.data = if self.step < self.n_step {
+            let mut data = vec![0f64; self.n_channel];
+            for (c, samples) in &self.channels {
+                data[*c] = samples[self.step];
+            }
+            self.step += 1;
+            Some(Arc::new(data))
+        } else {
+            None
+        };
+    }
+}
+
+impl<U> Write<U> for ScenarioSignals
+where
+    U: UniqueIdentifier<DataType = Vec<f64>>,
+{
+    fn write(&mut self) -> Option<Data<U>> {
+        self.data.clone().map(|data| data.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 1000.;
+
+    fn rms(x: &[f64]) -> f64 {
+        (x.iter().map(|x| x * x).sum::<f64>() / x.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn step() {
+        let step = Waveform::Step {
+            amplitude: 2.,
+            start: 0.5,
+        };
+        let x = step.sample(TimeBase::new(1000, FS));
+        assert!(x[..500].iter().all(|x| *x == 0.));
+        assert!(x[500..].iter().all(|x| *x == 2.));
+    }
+
+    #[test]
+    fn ramp() {
+        let ramp = Waveform::Ramp {
+            slope: 3.,
+            start: 0.25,
+            duration: Some(0.5),
+        };
+        let x = ramp.sample(TimeBase::new(1000, FS));
+        assert_eq!(x[250], 0.);
+        assert!((x[500] - 0.75).abs() < 1e-12);
+        assert!(x[750..].iter().all(|x| (x - 1.5).abs() < 1e-12));
+    }
+
+    #[test]
+    fn sinusoid() {
+        let sinusoid = Waveform::Sinusoid {
+            amplitude: 1.,
+            frequency: 10.,
+            phase: 0.,
+            start: 0.,
+        };
+        let x = sinusoid.sample(TimeBase::new(1000, FS));
+        // a quarter of a period is 25 samples
+        assert!((x[25] - 1.).abs() < 1e-12);
+        assert!(x[50].abs() < 1e-12);
+        assert!((rms(&x) - 0.5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn chirp() {
+        let chirp = Waveform::Chirp {
+            amplitude: 1.,
+            f0: 1.,
+            f1: 21.,
+            start: 0.,
+            duration: 1.,
+        };
+        let x = chirp.sample(TimeBase::new(2000, FS));
+        // phase 2pi(t + 10t^2)
+        let t = 0.3f64;
+        assert!((x[300] - (2. * PI * (t + 10. * t * t)).sin()).abs() < 1e-9);
+        assert!(x[1001..].iter().all(|x| *x == 0.));
+    }
+
+    #[test]
+    fn noise() {
+        for cutoff in [None, Some(5.)] {
+            let noise = Waveform::Noise {
+                rms: 2.,
+                cutoff,
+                seed: 7,
+                start: 0.,
+            };
+            let x = noise.sample(TimeBase::new(1_000_000, FS));
+            let rms = rms(&x);
+            assert!((rms - 2.).abs() < 0.1, "cutoff {cutoff:?}: noise RMS {rms}");
+        }
+    }
+
+    #[test]
+    fn time_base() {
+        let rate = 40;
+        let full = TimeBase::new(1001, FS).start(4.);
+        let decimated = full.decimate(rate);
+        assert_eq!(decimated.n_step, 26);
+        assert_eq!(decimated.sampling_frequency, FS / rate as f64);
+        for waveform in [
+            Waveform::Step {
+                amplitude: 1.,
+                start: 4.21,
+            },
+            Waveform::Ramp {
+                slope: 1.,
+                start: 4.1,
+                duration: None,
+            },
+            Waveform::Sinusoid {
+                amplitude: 1.,
+                frequency: 3.,
+                phase: 0.5,
+                start: 4.,
+            },
+            Waveform::Chirp {
+                amplitude: 1.,
+                f0: 1.,
+                f1: 2.,
+                start: 4.,
+                duration: 0.5,
+            },
+        ] {
+            let x = waveform.sample(full);
+            let y = waveform.sample(decimated);
+            for (k, y) in y.iter().enumerate() {
+                assert!((x[k * rate] - y).abs() < 1e-9, "{waveform:?} at #{k}");
+            }
+        }
+    }
+
+    #[test]
+    fn signals() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            [[m2_rbm]]
+            segment = 2
+            dof = 3
+            signal = { kind = "step", amplitude = 1.0 }
+            [[m2_rbm]]
+            segment = 2
+            dof = 3
+            signal = { kind = "step", amplitude = 2.0, start = 0.001 }
+            "#,
+        )
+        .unwrap();
+        let mut signals = scenario.m2_rbm(TimeBase::new(2, FS)).unwrap();
+        let mut data = vec![];
+        for _ in 0..3 {
+            signals.update();
+            data.push(<_ as Write<M2Rbm>>::write(&mut signals).map(|x| x.to_vec()));
+        }
+        let (a, b) = (data[0].as_ref().unwrap(), data[1].as_ref().unwrap());
+        assert_eq!(a.len(), 42);
+        assert_eq!((a[9], b[9]), (1., 3.));
+        assert!(data[2].is_none());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        for scenario in [
+            r#"
+            [[m2_rmb]]
+            segment = 2
+            dof = 3
+            signal = { kind = "step", amplitude = 1.0 }
+            "#,
+            r#"
+            [[m2_rbm]]
+            segment = 2
+            dof = 3
+            signal = { kind = "step", amplitude = 1.0, strat = 0.5 }
+            "#,
+            r#"
+            [[m2_rbm]]
+            segmnet = 2
+            dof = 3
+            signal = { kind = "step", amplitude = 1.0 }
+            "#,
+        ] {
+            assert!(toml::from_str::<Scenario>(scenario).is_err(), "{scenario}");
+        }
+    }
+
+    #[derive(interface::UID)]
+    enum M2Rbm {}
+}
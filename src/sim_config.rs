@@ -0,0 +1,332 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use skyangle::Conversion;
+
+use crate::config;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Open(io::Error),
+    Toml(toml::de::Error),
+    Invalid { field: &'static str, reason: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Open(error) => error.fmt(f),
+            ConfigError::Toml(error) => error.fmt(f),
+            ConfigError::Invalid { field, reason } => {
+                write!(f, "invalid simulation configuration `{field}`: {reason}")
+            }
+        }
+    }
+}
+impl Error for ConfigError {}
+impl From<io::Error> for ConfigError {
+    fn from(value: io::Error) -> Self {
+        Self::Open(value)
+    }
+}
+impl From<toml::de::Error> for ConfigError {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Toml(value)
+    }
+}
+
+/// Default configuration file
+pub const SIM_CONFIG: &str = "sim.toml";
+
+/// Integrated model runtime configuration
+///
+/// The configuration is loaded from a TOML file, any missing entry
+/// is set to the value of the corresponding constant in [config](crate::config)
+/// and any unknown entry is rejected
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    /// atmospheric turbulence on/off
+    pub atmosphere: bool,
+    /// simulation sampling frequency [Hz]
+    pub sampling_frequency: usize,
+    /// closed-loop duration [s]
+    pub duration: usize,
+    /// FEM and control systems bootstrapping duration [s]
+    pub bootstrapping_duration: usize,
+    pub m1: M1Config,
+    pub agws: AgwsConfig,
+    pub fsm: FsmConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct M1Config {
+    /// number of bending modes per segment
+    pub n_mode: usize,
+    /// M1 actuators sampling rate ratio
+    pub actuator_rate: usize,
+    pub edge_sensor: EdgeSensorConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EdgeSensorConfig {
+    pub rbm_integrator_gain: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgwsConfig {
+    pub sh48: Sh48Config,
+    pub sh24: Sh24Config,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sh48Config {
+    /// SH48 sampling rate ratio
+    pub rate: usize,
+    /// M1 bending modes integrator gain
+    pub integrator_gain: f64,
+    /// saved merge reconstructor that replaces the SH48 reconstructor whenever it is modified
+    pub reconstructor_watch: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sh24Config {
+    /// SH24 sampling rate ratio
+    pub rate: usize,
+    pub integrator_gain: f64,
+    /// SH24 pointing error (x,y) [mas]
+    pub pointing_error: Option<(f64, f64)>,
+    /// pickled reconstructor that replaces the SH24 to FSM reconstructor whenever it is modified
+    pub reconstructor_watch: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FsmConfig {
+    pub offload_integrator_gain: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            atmosphere: config::ATMOSPHERE,
+            sampling_frequency: 1000,
+            duration: 80,
+            bootstrapping_duration: 4,
+            m1: Default::default(),
+            agws: Default::default(),
+            fsm: Default::default(),
+        }
+    }
+}
+impl Default for M1Config {
+    fn default() -> Self {
+        Self {
+            n_mode: config::m1::segment::N_MODE,
+            actuator_rate: config::m1::segment::ACTUATOR_RATE,
+            edge_sensor: Default::default(),
+        }
+    }
+}
+impl Default for EdgeSensorConfig {
+    fn default() -> Self {
+        Self {
+            rbm_integrator_gain: config::m1::edge_sensor::RBM_INTEGRATOR_GAIN,
+        }
+    }
+}
+impl Default for Sh48Config {
+    fn default() -> Self {
+        Self {
+            rate: config::agws::sh48::RATE,
+            integrator_gain: config::agws::sh48::INTEGRATOR_GAIN,
+            reconstructor_watch: None,
+        }
+    }
+}
+impl Default for Sh24Config {
+    fn default() -> Self {
+        Self {
+            rate: config::agws::sh24::RATE,
+            integrator_gain: config::agws::sh24::INTEGRATOR_GAIN,
+            pointing_error: config::agws::sh24::POINTING_ERROR
+                .map(|(x, y)| (x.to_mas(), y.to_mas())),
+            reconstructor_watch: None,
+        }
+    }
+}
+impl Default for FsmConfig {
+    fn default() -> Self {
+        Self {
+            offload_integrator_gain: config::fsm::OFFLOAD_INTEGRATOR_GAIN,
+        }
+    }
+}
+
+impl SimConfig {
+    /// Loads the configuration from a TOML file
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let cfg: Self = toml::from_str(&fs::read_to_string(path.as_ref())?)?;
+        Ok(cfg)
+    }
+    /// Loads the configuration from the given TOML file,
+    /// or from [SIM_CONFIG] if it exists, otherwise returns the default configuration
+    ///
+    /// A file that is given but does not exist is an error
+    pub fn load(path: Option<impl AsRef<Path>>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::from_toml(path),
+            None if Path::new(SIM_CONFIG).exists() => Self::from_toml(SIM_CONFIG),
+            None => Ok(Default::default()),
+        }
+    }
+    /// Checks the configuration for inconsistent values
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.sampling_frequency == 0 {
+            return Err(ConfigError::Invalid {
+                field: "sampling_frequency",
+                reason: "must be strictly positive".into(),
+            });
+        }
+        if self.duration == 0 {
+            return Err(ConfigError::Invalid {
+                field: "duration",
+                reason: "must be strictly positive".into(),
+            });
+        }
+        if self.m1.n_mode == 0 || self.m1.n_mode > config::m1::segment::N_RAW_MODE {
+            return Err(ConfigError::Invalid {
+                field: "m1.n_mode",
+                reason: format!(
+                    "expected a value in [1,{}], found {}",
+                    config::m1::segment::N_RAW_MODE,
+                    self.m1.n_mode
+                ),
+            });
+        }
+        for (field, rate) in [
+            ("m1.actuator_rate", self.m1.actuator_rate),
+            ("agws.sh48.rate", self.agws.sh48.rate),
+            ("agws.sh24.rate", self.agws.sh24.rate),
+        ] {
+            if rate == 0 {
+                return Err(ConfigError::Invalid {
+                    field,
+                    reason: "must be strictly positive".into(),
+                });
+            }
+        }
+        for (field, gain) in [
+            (
+                "m1.edge_sensor.rbm_integrator_gain",
+                self.m1.edge_sensor.rbm_integrator_gain,
+            ),
+            ("agws.sh24.integrator_gain", self.agws.sh24.integrator_gain),
+            ("agws.sh48.integrator_gain", self.agws.sh48.integrator_gain),
+            (
+                "fsm.offload_integrator_gain",
+                self.fsm.offload_integrator_gain,
+            ),
+        ] {
+            if !gain.is_finite() || gain < 0. {
+                return Err(ConfigError::Invalid {
+                    field,
+                    reason: format!("expected a finite positive gain, found {gain}"),
+                });
+            }
+        }
+        if let Some((x, y)) = self.agws.sh24.pointing_error {
+            if !(x.is_finite() && y.is_finite()) {
+                return Err(ConfigError::Invalid {
+                    field: "agws.sh24.pointing_error",
+                    reason: format!("expected finite values, found ({x},{y})"),
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Number of bootstrapping time steps
+    pub fn n_bootstrapping(&self) -> usize {
+        self.sampling_frequency * self.bootstrapping_duration
+    }
+    /// Total number of time steps
+    pub fn n_sim(&self) -> usize {
+        self.n_bootstrapping() + self.sampling_frequency * self.duration + 1
+    }
+    /// SH24 pointing error (x,y) [rd]
+    pub fn sh24_pointing_error(&self) -> Option<(f64, f64)> {
+        self.agws
+            .sh24
+            .pointing_error
+            .map(|(x, y)| (x.from_mas(), y.from_mas()))
+    }
+}
+
+impl Display for SimConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Simulation configuration:")?;
+        writeln!(f, " * atmosphere: {}", self.atmosphere)?;
+        writeln!(
+            f,
+            " * sampling frequency: {}Hz, duration: {}s (+{}s bootstrapping)",
+            self.sampling_frequency, self.duration, self.bootstrapping_duration
+        )?;
+        writeln!(f, " * M1 bending modes: {}", self.m1.n_mode)?;
+        writeln!(
+            f,
+            " * rates: M1 actuators 1:{}, SH48 1:{}, SH24 1:{}",
+            self.m1.actuator_rate, self.agws.sh48.rate, self.agws.sh24.rate
+        )?;
+        writeln!(
+            f,
+            " * gains: M1 edge sensors {}, SH24 {}, SH48 {}, FSM off-load {}",
+            self.m1.edge_sensor.rbm_integrator_gain,
+            self.agws.sh24.integrator_gain,
+            self.agws.sh48.integrator_gain,
+            self.fsm.offload_integrator_gain
+        )?;
+        if let Some((x, y)) = self.agws.sh24.pointing_error {
+            writeln!(f, " * SH24 pointing error: ({x},{y})mas")?;
+        }
+        if let Some(path) = &self.agws.sh48.reconstructor_watch {
+            writeln!(f, " * SH48 reconstructor watched in {path:?}")?;
+        }
+        if let Some(path) = &self.agws.sh24.reconstructor_watch {
+            writeln!(f, " * SH24 reconstructor watched in {path:?}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file() {
+        let path = std::env::temp_dir().join("gmt-ns-im_missing_sim.toml");
+        assert!(matches!(
+            SimConfig::load(Some(&path)),
+            Err(ConfigError::Open(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_field() {
+        let toml = "sampling_frequency = 1000\n[agws.sh48]\nintegrator_gian = 0.5\n";
+        assert!(toml::from_str::<SimConfig>(toml).is_err());
+        let toml = "sampling_frequency = 1000\n[agws.sh48]\nintegrator_gain = 0.5\n";
+        let sim_config: SimConfig = toml::from_str(toml).unwrap();
+        assert_eq!(sim_config.agws.sh48.integrator_gain, 0.5);
+    }
+}
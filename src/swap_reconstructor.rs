@@ -0,0 +1,235 @@
+use std::{
+    fmt::{self, Display},
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+};
+
+use faer::MatRef;
+use gmt_dos_clients_crseo::calibration::{Reconstructor, algebra::CalibProps};
+use interface::{Data, Read, UniqueIdentifier, Update, Write};
+
+use crate::{MergeError, ReconstructorMatrix, merge::Watch};
+
+/// Hot-swappable [Reconstructor]
+///
+/// Wraps a [Reconstructor] that can be replaced while the model is running,
+/// either with the pseudo-inverses sent to the [ReconstructorMatrix] input
+/// or with the pickled [Reconstructor] saved in a watched file (see [SwapReconstructor::watch]).
+/// The replacements are applied at the beginning of the next update,
+/// i.e. at the next sensor frame.
+///
+/// Reads and writes the same data as the wrapped [Reconstructor].
+#[derive(Debug)]
+pub struct SwapReconstructor {
+    recon: Reconstructor,
+    pending_matrix: Option<Arc<Vec<Vec<f64>>>>,
+    watch: Option<Watch>,
+}
+
+impl SwapReconstructor {
+    pub fn new(recon: Reconstructor) -> Self {
+        Self {
+            recon,
+            pending_matrix: None,
+            watch: None,
+        }
+    }
+    /// Number of modes of the calibration of each segment
+    pub fn estimate_sizes(&self) -> Vec<usize> {
+        self.recon.calib().map(|c| c.n_cols()).collect()
+    }
+    /// Replaces the reconstructor with the one pickled in `path` whenever the file is modified
+    ///
+    /// The file is checked at the beginning of each update,
+    /// a reconstructor that cannot be loaded or with different estimate sizes is ignored
+    /// until the file is modified again
+    pub fn watch(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.watch = Some(Watch::new(path));
+        self
+    }
+    /// Replaces the pseudo-inverse of each segment
+    ///
+    /// One column-major matrix per segment, of the size of the transpose of the segment calibration
+    pub fn set_reconstructor_matrix(
+        &mut self,
+        matrix: &[Vec<f64>],
+    ) -> Result<&mut Self, MergeError> {
+        let shapes: Vec<(usize, usize)> = self
+            .recon
+            .calib_pinv()
+            .map(|(c, _)| (c.n_cols(), c.n_rows()))
+            .collect();
+        for (i, (n_rows, n_cols)) in shapes.iter().enumerate() {
+            let found = matrix.get(i).map_or(0, |m| m.len());
+            if found != n_rows * n_cols {
+                return Err(MergeError::ReconstructorMatrix {
+                    segment: i + 1,
+                    expected: n_rows * n_cols,
+                    found,
+                });
+            }
+        }
+        self.recon.pinv().zip(matrix.iter().zip(&shapes)).for_each(
+            |(p, (m, &(n_rows, n_cols)))| {
+                p.transform(|_| MatRef::from_column_major_slice(m, n_rows, n_cols).to_owned())
+            },
+        );
+        Ok(self)
+    }
+    /// Replaces the reconstructor with another one with the same estimate sizes
+    fn swap(&mut self, path: &Path) -> Result<(), MergeError> {
+        let other: Reconstructor =
+            serde_pickle::from_reader(BufReader::new(File::open(path)?), Default::default())?;
+        let found: Vec<usize> = other.calib().map(|c| c.n_cols()).collect();
+        let expected = self.estimate_sizes();
+        if found != expected {
+            return Err(MergeError::EstimateSizes {
+                path: path.to_path_buf(),
+                expected,
+                found,
+            });
+        }
+        self.recon = other;
+        Ok(())
+    }
+    /// Applies the pending replacements, called at the beginning of each update
+    fn hot_swap(&mut self) {
+        if let Some(matrix) = self.pending_matrix.take() {
+            match self.set_reconstructor_matrix(&matrix) {
+                Ok(_) => log::info!("reconstructor matrix replaced"),
+                Err(e) => log::warn!("reconstructor matrix ignored: {e}"),
+            }
+        }
+        let Some(path) = self.watch.as_mut().and_then(Watch::poll) else {
+            return;
+        };
+        match self.swap(&path) {
+            Ok(_) => log::info!("reconstructor replaced with {path:?}"),
+            Err(e) => log::warn!("reconstructor not replaced with {path:?}: {e}"),
+        }
+    }
+}
+
+impl Update for SwapReconstructor {
+    fn update(&mut self) {
+        self.hot_swap();
+        self.recon.update();
+    }
+}
+
+impl Read<ReconstructorMatrix> for SwapReconstructor {
+    fn read(&mut self, data: Data<ReconstructorMatrix>) {
+        self.pending_matrix = Some(data.into_arc());
+    }
+}
+
+impl<U> Read<U> for SwapReconstructor
+where
+    U: UniqueIdentifier<DataType = Vec<f64>>,
+    Reconstructor: Read<U>,
+{
+    fn read(&mut self, data: Data<U>) {
+        <Reconstructor as Read<U>>::read(&mut self.recon, data);
+    }
+}
+
+impl<U> Write<U> for SwapReconstructor
+where
+    U: UniqueIdentifier<DataType = Vec<f64>>,
+    Reconstructor: Write<U>,
+{
+    fn write(&mut self) -> Option<Data<U>> {
+        <Reconstructor as Write<U>>::write(&mut self.recon)
+    }
+}
+
+impl Display for SwapReconstructor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.recon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        error::Error,
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use gmt_dos_clients_io::optics::SensorData;
+
+    use super::*;
+    use crate::fixtures::{N_SEGMENT, calibrations, pickle, random, sensor};
+
+    fn reconstruct(recon: &mut SwapReconstructor, slopes: Vec<f64>) -> Vec<f64> {
+        <_ as Read<SensorData>>::read(recon, Data::new(slopes));
+        recon.update();
+        <_ as Write<SensorData>>::write(recon).unwrap().to_vec()
+    }
+
+    #[test]
+    fn hot_swap() -> Result<(), Box<dyn Error>> {
+        let a = pickle("gmt-ns-im_swap_a.pkl", calibrations(20, 3, 1))?;
+        let mut recon = SwapReconstructor::new(serde_pickle::from_reader(
+            File::open(&a)?,
+            Default::default(),
+        )?);
+        let path = std::env::temp_dir().join("gmt-ns-im_swap.pkl");
+        let _ = fs::remove_file(&path);
+        recon.watch(&path);
+        let t0 = SystemTime::now();
+        let modes = random(N_SEGMENT * 3, 7);
+
+        // different estimate sizes
+        fs::copy(
+            pickle("gmt-ns-im_swap_c.pkl", calibrations(20, 2, 1))?,
+            &path,
+        )?;
+        File::options().write(true).open(&path)?.set_modified(t0)?;
+        let b = calibrations(20, 3, 10);
+        let estimate = reconstruct(&mut recon, sensor(&b, &modes));
+        assert!(
+            estimate
+                .iter()
+                .zip(&modes)
+                .any(|(e, m)| (e - m).abs() > 1e-6)
+        );
+
+        fs::copy(
+            pickle("gmt-ns-im_swap_b.pkl", calibrations(20, 3, 10))?,
+            &path,
+        )?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(t0 + Duration::from_secs(1))?;
+        let estimate = reconstruct(&mut recon, sensor(&b, &modes));
+        assert!(
+            estimate
+                .iter()
+                .zip(&modes)
+                .all(|(e, m)| (e - m).abs() < 1e-9)
+        );
+
+        // the matrix is applied at the next update
+        <_ as Read<ReconstructorMatrix>>::read(
+            &mut recon,
+            Data::new(vec![vec![0.; 3 * 20]; N_SEGMENT]),
+        );
+        let estimate = reconstruct(&mut recon, sensor(&b, &modes));
+        assert!(estimate.iter().all(|e| *e == 0.));
+
+        assert!(matches!(
+            recon.set_reconstructor_matrix(&[vec![0.; 3 * 20]]),
+            Err(MergeError::ReconstructorMatrix {
+                segment: 2,
+                expected: 60,
+                found: 0
+            })
+        ));
+        Ok(())
+    }
+}
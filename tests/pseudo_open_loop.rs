@@ -0,0 +1,107 @@
+use std::error::Error;
+
+use gmt_dos_clients_crseo::calibration::{Calib, CalibrationMode, Reconstructor};
+use gmt_dos_clients_io::{
+    mount::MountSetPoint,
+    optics::{M1Modes, SensorData},
+};
+use gmt_ns_im::{PseudoOpenLoop, PseudoSensorData};
+use interface::{Data, Read, Update, Write};
+
+#[path = "../src/fixtures.rs"]
+mod fixtures;
+use fixtures::{N_SEGMENT, calibrations, random, sensor};
+
+const N_SLOPE: usize = 20;
+const N_MODE: usize = 3;
+const INTEGRATION: usize = 5;
+const LATENCY: usize = 2;
+
+#[test]
+fn pseudo_open_loop() -> Result<(), Box<dyn Error>> {
+    let calibs = calibrations(N_SLOPE, N_MODE, 1);
+    let mut pol = PseudoOpenLoop::<M1Modes>::new(Reconstructor::new(calibs.clone()))
+        .integration(INTEGRATION)
+        .latency(LATENCY);
+    assert_eq!(pol.command_size(), N_SEGMENT * N_MODE);
+
+    // open-loop modes
+    let modes = random(N_SEGMENT * N_MODE, 42);
+    let open_loop = sensor(&calibs, &modes);
+
+    let cmds: Vec<Vec<f64>> = (0..20)
+        .map(|t| random(N_SEGMENT * N_MODE, 100 + t))
+        .collect();
+    for t in 0..cmds.len() {
+        <_ as Read<M1Modes>>::read(&mut pol, Data::new(cmds[t].clone()));
+        if t < INTEGRATION + LATENCY - 1 {
+            continue;
+        }
+        // the sensor integrates the residual over the window ending LATENCY commands ago
+        let window = &cmds[t + 1 - LATENCY - INTEGRATION..=t - LATENCY];
+        let residual: Vec<f64> = modes
+            .iter()
+            .enumerate()
+            .map(|(k, x)| x - window.iter().map(|c| c[k]).sum::<f64>() / INTEGRATION as f64)
+            .collect();
+        <_ as Read<SensorData>>::read(&mut pol, Data::new(sensor(&calibs, &residual)));
+        pol.update();
+        let slopes = <_ as Write<PseudoSensorData>>::write(&mut pol).unwrap();
+        let error = slopes
+            .iter()
+            .zip(&open_loop)
+            .map(|(s, x)| (s - x).abs())
+            .fold(0f64, f64::max);
+        assert!(
+            error < 1e-12,
+            "step #{t}: pseudo open-loop error: {error:e}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn command_size() -> Result<(), Box<dyn Error>> {
+    let calibs = calibrations(N_SLOPE, N_MODE, 1);
+    let mut pol = PseudoOpenLoop::<M1Modes>::new(Reconstructor::new(calibs.clone()));
+    let sensor_data = random(N_SEGMENT * N_SLOPE, 7);
+    let modes = random(N_SEGMENT * N_MODE, 42);
+
+    // a command of the wrong size is ignored
+    <_ as Read<M1Modes>>::read(&mut pol, Data::new(modes[1..].to_vec()));
+    <_ as Read<SensorData>>::read(&mut pol, Data::new(sensor_data.clone()));
+    pol.update();
+    let slopes = <_ as Write<PseudoSensorData>>::write(&mut pol).unwrap();
+    assert_eq!(*slopes, sensor_data);
+    assert_eq!(pol.rejected_commands(), 1);
+
+    <_ as Read<M1Modes>>::read(&mut pol, Data::new(modes.clone()));
+    pol.update();
+    let slopes = <_ as Write<PseudoSensorData>>::write(&mut pol).unwrap();
+    let expected: Vec<f64> = sensor_data
+        .iter()
+        .zip(sensor(&calibs, &modes))
+        .map(|(s, c)| s + c)
+        .collect();
+    let error = slopes
+        .iter()
+        .zip(&expected)
+        .map(|(s, x)| (s - x).abs())
+        .fold(0f64, f64::max);
+    assert!(error < 1e-12, "pseudo open-loop error: {error:e}");
+
+    // the mount set-point is checked against the mount calibration
+    let n_data = 2 * N_SLOPE;
+    let mount = Calib::builder()
+        .c(random(n_data * 2, 3))
+        .n_cols(2)
+        .mask(vec![true; n_data])
+        .mode(CalibrationMode::modes(2, 1e-6))
+        .build();
+    let mut pol = PseudoOpenLoop::<MountSetPoint>::new(Reconstructor::new(vec![mount]));
+    assert_eq!(pol.command_size(), 2);
+    <_ as Read<MountSetPoint>>::read(&mut pol, Data::new(vec![0.; 3]));
+    <_ as Read<MountSetPoint>>::read(&mut pol, Data::new(vec![0.; 2]));
+    assert_eq!(pol.rejected_commands(), 1);
+    Ok(())
+}
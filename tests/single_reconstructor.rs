@@ -0,0 +1,66 @@
+use std::{error::Error, path::PathBuf, sync::Arc};
+
+use gmt_dos_clients_crseo::calibration::CalibrationMode;
+use gmt_dos_clients_io::optics::{M1Modes, SensorData};
+use gmt_ns_im::{MergeReconstructor, ReconstructorMatrix};
+use interface::{Data, Read, Update, Write};
+
+#[path = "../src/fixtures.rs"]
+mod fixtures;
+use fixtures::{N_SEGMENT, calibrations, pickle, random, sensor};
+
+const N_SLOPE: usize = 20;
+const N_MODE: usize = 3;
+
+/// Pickles a synthetic reconstructor and returns its file, the slopes and the modes
+fn synthetic(name: &str) -> Result<(PathBuf, Vec<f64>, Vec<f64>), Box<dyn Error>> {
+    let calibs = calibrations(N_SLOPE, N_MODE, 1);
+    let modes = random(N_SEGMENT * N_MODE, 42);
+    let slopes = sensor(&calibs, &modes);
+    Ok((pickle(name, calibs)?, slopes, modes))
+}
+
+fn reconstruct(
+    recon: &mut MergeReconstructor<CalibrationMode, M1Modes>,
+    slopes: &[f64],
+) -> Arc<Vec<f64>> {
+    <_ as Read<SensorData>>::read(recon, Data::new(slopes.to_vec()));
+    recon.update();
+    <_ as Write<M1Modes>>::write(recon).unwrap().into_arc()
+}
+
+#[test]
+fn single_reconstructor() -> Result<(), Box<dyn Error>> {
+    let (path, slopes, modes) = synthetic("gmt-ns-im_single_reconstructor.pkl")?;
+
+    let mut single = MergeReconstructor::single(&path)?;
+    println!("{single}");
+    assert_eq!(single.estimate_sizes(), &[N_SEGMENT * N_MODE]);
+
+    let estimate = reconstruct(&mut single, &slopes);
+    let error = estimate
+        .iter()
+        .zip(&modes)
+        .map(|(e, x)| (e - x).abs())
+        .fold(0f64, f64::max);
+    assert!(error < 1e-9, "estimate error: {error:e}");
+    Ok(())
+}
+
+#[test]
+fn reconstructor_matrix() -> Result<(), Box<dyn Error>> {
+    let (path, slopes, _) = synthetic("gmt-ns-im_reconstructor_matrix.pkl")?;
+    let mut single = MergeReconstructor::single(&path)?;
+
+    // the replacement is applied at the next update
+    let zeros = vec![vec![0f64; N_MODE * N_SLOPE]; N_SEGMENT];
+    <_ as Read<ReconstructorMatrix>>::read(&mut single, Data::new(zeros));
+    let estimate = reconstruct(&mut single, &slopes);
+    assert!(estimate.iter().all(|x| *x == 0.));
+
+    // a matrix of the wrong size is ignored
+    <_ as Read<ReconstructorMatrix>>::read(&mut single, Data::new(vec![vec![0f64; 1]]));
+    let estimate = reconstruct(&mut single, &slopes);
+    assert!(estimate.iter().all(|x| *x == 0.));
+    Ok(())
+}